@@ -0,0 +1,43 @@
+#![no_main]
+
+#[allow(dead_code)]
+#[path = "../../src/entry.rs"]
+mod entry;
+#[allow(dead_code)]
+#[path = "../../src/time.rs"]
+mod time;
+
+use libfuzzer_sys::fuzz_target;
+use utmp::reader::UtmpReader;
+use utmp::utmp::Utmp;
+use entry::{BootEntry, FindingEntry, SessionEntry, UtmpEntry};
+use time::Zone;
+
+// 偏移最大的时区，时间在chrono范围的边上时最容易出错
+const ZONES: [Zone; 2] = [Zone::Utc, Zone::Named(chrono_tz::Pacific::Kiritimati)];
+
+fuzz_target!(|data: &[u8]| {
+    // 宽松读取：跳过无效记录，让后面的分析也拿到任意内容。
+    let records = UtmpReader::new(data).filter_map(Result::ok).collect::<Vec<Utmp>>();
+    let (_, all) = match utmp::take_all_records(data) {
+        Ok(res) => res,
+        Err(_) => return,
+    };
+
+    for zone in ZONES {
+        for record in &all {
+            if let Ok(entry) = UtmpEntry::with_zone(record.clone(), zone) {
+                let _ = tabled::Table::new([entry]).to_string();
+            }
+        }
+        for session in utmp::session::sessions(records.iter().cloned()) {
+            let _ = tabled::Table::new([SessionEntry::with_zone(&session, zone)]).to_string();
+        }
+        for period in utmp::boot::boot_history(records.iter().cloned()) {
+            let _ = tabled::Table::new([BootEntry::with_zone(&period, zone)]).to_string();
+        }
+    }
+    for finding in utmp::anomaly::analyze(&records) {
+        let _ = FindingEntry::from(&finding);
+    }
+});
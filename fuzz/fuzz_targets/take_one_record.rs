@@ -0,0 +1,23 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use utmp::UT_RECORDSIZE;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((rest, utmp)) = utmp::take_one_record(data) {
+        assert_eq!(rest.len(), data.len() - UT_RECORDSIZE);
+        // 写回的字节必须与读入的一致
+        assert_eq!(&utmp.as_bytes()[..], &data[..UT_RECORDSIZE]);
+        let _ = utmp.record_type();
+        let _ = (utmp.user(), utmp.host(), utmp.line(), utmp.id());
+        let _ = (utmp.timestamp(), utmp.addr());
+    }
+    let _ = utmp::parse_records(data);
+    let _ = utmp::audit::audit(data);
+    for layout in utmp::Layout::all() {
+        let _ = layout.parse_records(data);
+        let _ = utmp::audit::audit_with(layout, data);
+    }
+    let _ = utmp::utx::parse_records(data);
+    let _ = utmp::utx::detect(data);
+});
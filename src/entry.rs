@@ -1,13 +1,15 @@
 use chrono::prelude::*;
 use std::convert::TryFrom;
-use std::fmt::format;
-use utmp::{utmp::Utmp, ulity};
+use std::time::Duration;
+use utmp::{utmp::Utmp, UtType, session::Session, boot::BootPeriod, audit::AuditIssue, anomaly::Finding, carve::Carved};
 use thiserror::Error;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
-use tabled::{Tabled, Table};
+use std::net::IpAddr;
+use tabled::Tabled;
+use serde::{Deserialize, Serialize};
 use base62;
+use crate::time::Zone;
 
-#[derive(Tabled)]
+#[derive(Tabled, Serialize, Deserialize)]
 #[derive(Clone, Default, Debug)]
 pub struct UtmpEntry {
     #[tabled(rename = "Type Id", skip)]
@@ -40,10 +42,199 @@ pub struct UtmpEntry {
     sessionid: Option<i32>,
     #[tabled(display_with = "display_option")]
     #[tabled(rename = "Time")]
-    pub time: Option<NaiveDateTime>,
+    pub time: Option<DateTime<FixedOffset>>,
     #[tabled(display_with = "display_option")]
     #[tabled(rename = "IP Addr")]
     ipaddr: Option<IpAddr>,
+    // 记录在文件中的字节偏移，不定长的格式和数据库中没有
+    #[tabled(display_with = "display_option", skip)]
+    #[tabled(rename = "Offset")]
+    pub offset: Option<u64>,
+}
+
+#[derive(Tabled, Serialize)]
+#[derive(Clone, Debug)]
+pub struct SessionEntry {
+    #[tabled(rename = "Username")]
+    username: String,
+    #[tabled(rename = "Terminal")]
+    line: String,
+    #[tabled(rename = "Hostname")]
+    hostname: String,
+    #[tabled(display_with = "display_option")]
+    #[tabled(rename = "Login")]
+    login: Option<DateTime<FixedOffset>>,
+    #[tabled(display_with = "display_option")]
+    #[tabled(rename = "Logout")]
+    logout: Option<DateTime<FixedOffset>>,
+    #[tabled(rename = "End")]
+    end: String,
+    #[tabled(display_with = "display_option")]
+    #[tabled(rename = "Duration")]
+    duration: Option<String>,
+}
+
+impl SessionEntry {
+    /// The session with its times in `zone`.
+    pub fn with_zone(from: &Session, zone: Zone) -> Self {
+        SessionEntry {
+            username: from.login.user(),
+            line: from.login.line(),
+            hostname: from.login.host(),
+            login: from.start().and_then(|t| zone.localize(t)),
+            logout: from.end_time().and_then(|t| zone.localize(t)),
+            end: from.end.to_string(),
+            duration: from.duration().map(format_duration),
+        }
+    }
+}
+
+#[derive(Tabled, Serialize)]
+#[derive(Clone, Debug)]
+pub struct BootEntry {
+    #[tabled(rename = "Kernel")]
+    kernel: String,
+    #[tabled(display_with = "display_option")]
+    #[tabled(rename = "Boot")]
+    boot: Option<DateTime<FixedOffset>>,
+    #[tabled(display_with = "display_option")]
+    #[tabled(rename = "Shutdown")]
+    shutdown: Option<DateTime<FixedOffset>>,
+    #[tabled(rename = "End")]
+    end: String,
+    #[tabled(display_with = "display_option")]
+    #[tabled(rename = "Uptime")]
+    uptime: Option<String>,
+    #[tabled(rename = "Runlevels")]
+    runlevels: String,
+}
+
+impl BootEntry {
+    /// The boot period with its times in `zone`.
+    pub fn with_zone(from: &BootPeriod, zone: Zone) -> Self {
+        BootEntry {
+            kernel: from.kernel(),
+            boot: from.start().and_then(|t| zone.localize(t)),
+            shutdown: from.end_time().and_then(|t| zone.localize(t)),
+            end: from.end.to_string(),
+            uptime: from.uptime().map(format_duration),
+            // 例如： N→5 (07:00:01), 5→0 (09:30:12)
+            runlevels: from.runlevels.iter()
+                .map(|r| format!("{}→{} ({})",
+                                 r.previous().unwrap_or('N'),
+                                 r.current().unwrap_or('N'),
+                                 display_option(&r.time().and_then(|t| zone.localize(t)).map(|t| t.format("%H:%M:%S")))))
+                .collect::<Vec<_>>()
+                .join(", "),
+        }
+    }
+}
+
+#[derive(Tabled, Serialize)]
+#[derive(Clone, Debug)]
+pub struct AuditEntry {
+    #[tabled(rename = "Offset")]
+    offset: u64,
+    #[tabled(rename = "Record")]
+    record: usize,
+    #[tabled(rename = "Issue")]
+    issue: String,
+}
+
+impl From<&AuditIssue> for AuditEntry {
+    fn from(from: &AuditIssue) -> Self {
+        AuditEntry {
+            offset: from.offset,
+            record: from.record,
+            issue: from.kind.to_string(),
+        }
+    }
+}
+
+#[derive(Tabled, Serialize)]
+#[derive(Clone, Debug)]
+pub struct FindingEntry {
+    #[tabled(rename = "Severity")]
+    severity: String,
+    #[tabled(rename = "Records")]
+    records: String,
+    #[tabled(rename = "Finding")]
+    finding: String,
+}
+
+impl From<&Finding> for FindingEntry {
+    fn from(from: &Finding) -> Self {
+        FindingEntry {
+            severity: from.severity.to_string(),
+            records: from.records.iter().map(|r| r.to_string()).collect::<Vec<_>>().join(", "),
+            finding: from.kind.to_string(),
+        }
+    }
+}
+
+#[derive(Tabled, Serialize)]
+#[derive(Clone, Debug)]
+pub struct CarvedEntry {
+    // JSON中使用entry的offset
+    #[tabled(rename = "Offset")]
+    #[serde(skip)]
+    offset: u64,
+    #[tabled(rename = "Confidence")]
+    confidence: String,
+    #[tabled(inline)]
+    #[serde(flatten)]
+    entry: UtmpEntry,
+}
+
+impl CarvedEntry {
+    /// The carved record with its time in `zone`.
+    pub fn with_zone(from: &Carved, zone: Zone) -> Result<Self, UtmpError> {
+        let mut entry = UtmpEntry::with_zone(from.record.clone(), zone)?;
+        entry.offset = Some(from.offset);
+        Ok(CarvedEntry {
+            offset: from.offset,
+            confidence: format!("{:.2}", from.confidence),
+            entry,
+        })
+    }
+}
+
+/// How many records have a value of a field, for `stats`.
+#[derive(Tabled, Serialize)]
+#[derive(Clone, Debug)]
+pub struct StatsEntry {
+    #[tabled(rename = "By")]
+    pub by: String,
+    #[tabled(rename = "Value")]
+    pub value: String,
+    #[tabled(rename = "Count")]
+    pub count: usize,
+    #[tabled(display_with = "display_option")]
+    #[tabled(rename = "First")]
+    pub first: Option<DateTime<FixedOffset>>,
+    #[tabled(display_with = "display_option")]
+    #[tabled(rename = "Last")]
+    pub last: Option<DateTime<FixedOffset>>,
+}
+
+/// A record and the file it was read from, for `export`.
+#[derive(Tabled, Serialize)]
+#[derive(Clone, Debug)]
+pub struct ExportEntry {
+    #[tabled(rename = "File")]
+    pub file: String,
+    #[tabled(inline)]
+    #[serde(flatten)]
+    pub entry: UtmpEntry,
+}
+
+/// 与last相同的格式： [天数+]时:分
+fn format_duration(d: Duration) -> String {
+    let minutes = d.as_secs() / 60;
+    match minutes / (24 * 60) {
+        0 => format!("{:02}:{:02}", minutes / 60, minutes % 60),
+        days => format!("{}+{:02}:{:02}", days, minutes / 60 % 24, minutes % 60),
+    }
 }
 
 fn display_option<T>(o: &Option<T>) -> String
@@ -70,22 +261,17 @@ impl TryFrom<Utmp> for UtmpEntry {
     type Error = UtmpError;
 
     fn try_from(from: Utmp) -> Result<Self, UtmpError> {
-        const ENTRY_TYPES: [&str; 12] = [
-            "EMPTY",
-            "RUN_LVL",
-            "BOOT_TIME",
-            "NEW_TIME",
-            "OLD_TIME",
-            "INIT_PROCESS",
-            "LOGIN_PROCESS",
-            "USER_PROCESS",
-            "DEAD_PROCESS",
-            "ACCOUNTING",
-            "SIGNATURE",
-            "SHUTDOWN_TIME"
-        ];
+        UtmpEntry::with_zone(from, Zone::Utc)
+    }
+}
+
+impl UtmpEntry {
+    /// The record with its time in `zone`.
+    pub fn with_zone(from: Utmp, zone: Zone) -> Result<Self, UtmpError> {
+        let ut_type = from.record_type()
+            .map_err(|e| UtmpError::ErrDuringEntryParse(e.to_string()))?;
         let mut tmpentry: UtmpEntry = UtmpEntry{
-            typeid: 0,
+            typeid: from.ut_type,
             unioncode: "".to_string(),
             typestr: "".to_string(),
             pid: None,
@@ -97,57 +283,50 @@ impl TryFrom<Utmp> for UtmpEntry {
             exitstatus: None,
             sessionid: None,
             time: None,
-            ipaddr: None
+            ipaddr: None,
+            offset: None,
         };
-        match from.ut_type {
-            0 => {
-                tmpentry.typeid = 0;
-                // tmpentry.typestr = ENTRY_TYPES[i as usize].to_string();
-                tmpentry.typestr = format!("{:2} - {}",0,ENTRY_TYPES[0usize]);
-                return Ok(tmpentry);
-            },
-            i @ 1..=11 => {
-                tmpentry.typeid = i;
-                // tmpentry.typestr = ENTRY_TYPES[i as usize].to_string();
-                tmpentry.typestr = format!("{:2} - {}",i,ENTRY_TYPES[i as usize]);
-            },
-            ii => {
-                return  Err(UtmpError::ErrDuringEntryParse(format!("ut_type({}) is invalid.",ii,)))
-            }
+        tmpentry.typestr = format!("{:2} - {}", tmpentry.typeid, ut_type);
+        if ut_type == UtType::Empty {
+            return Ok(tmpentry);
         }
-        tmpentry.unioncode = base62::encode(u64::from(from.ut_time_sec) * u64::from(u32::MAX) + from.ut_time_usec as u64);
+        tmpentry.unioncode = unioncode(from.ut_time_sec, from.ut_time_usec);
         tmpentry.pid = Some(from.ut_pid);
-        tmpentry.line = Some(ulity::extract_string(&from.ut_line));
-        tmpentry.terminalid = Some(ulity::extract_string(&from.ut_id));
-        tmpentry.username = Some(ulity::extract_string(&from.ut_user));
-        tmpentry.hostname = Some(ulity::extract_string(&from.ut_host));
+        tmpentry.line = Some(from.line());
+        tmpentry.terminalid = Some(from.id());
+        tmpentry.username = Some(from.user());
+        tmpentry.hostname = Some(from.host());
         tmpentry.terminationstatus = Some(from.ut_termination);
         tmpentry.exitstatus = Some(from.ut_exit);
         tmpentry.sessionid = Some(from.ut_session);
-        tmpentry.time = Some(NaiveDateTime::from_timestamp(from.ut_time_sec as i64, from.ut_time_usec));
-        let tmpipv6u8: [u8;16] = from.ut_addr_v6.iter()
-            .map(|a|a.to_ne_bytes())
-            .flatten()
-            .collect::<Vec<u8>>()
-            .try_into()
-            .unwrap();
-        tmpentry.ipaddr = {
-            if from.ut_addr_v6[1..4] != [0,0,0] {
-                Some(IpAddr::V6(Ipv6Addr::from(tmpipv6u8)))
-            } else if from.ut_addr_v6[0] != 0 {
-                // tracing::debug!("from.ut_addr_v6[0] {:?}", from.ut_addr_v6[0]);
-                Some(IpAddr::V4(Ipv4Addr::from(from.ut_addr_v6[0])))
-            }
-            else {
-                None
-            }};
+        tmpentry.time = from.timestamp().and_then(|t| zone.localize(t));
+        tmpentry.ipaddr = from.addr();
         Ok(tmpentry)
     }
 }
 
-#[test]
-fn test_base62() {
-    // let (ut_time_sec, ut_time_usec) = ()
+/// 由时间得到的记录编号，用于 `-s` 查找。
+///
+/// 与32位时间时的编号相同：秒数乘以 `u32::MAX` 加微秒数。负的时间按补码回绕，仍各不相同。
+fn unioncode(sec: i64, usec: i64) -> String {
+    base62::encode((i128::from(sec) * i128::from(u32::MAX) + i128::from(usec)) as u128)
+}
 
+#[test]
+fn test_entry_json() {
+    let utmp = utmp::parse_records(include_bytes!("../files4test/wtmp")).unwrap().remove(4);
+    let entry = UtmpEntry::try_from(utmp).unwrap();
+    let json = serde_json::to_string(&entry).unwrap();
+    assert!(json.contains(r#""time":"2022-08-12T04:05:41.288718Z","ipaddr":"110.184.136.217""#));
+    let back: UtmpEntry = serde_json::from_str(&json).unwrap();
+    assert_eq!(serde_json::to_string(&back).unwrap(), json);
+}
 
+#[test]
+fn test_base62() {
+    // 32位时间的编号不变
+    assert_eq!(unioncode(1_629_861_096, 980_439), base62::encode(1_629_861_096u64 * u64::from(u32::MAX) + 980_439));
+    // 2106年以后不再溢出
+    assert_eq!(unioncode(5_000_000_000, 0), base62::encode(5_000_000_000u128 * u128::from(u32::MAX)));
+    assert_ne!(unioncode(-1, 0), unioncode(1, 0));
 }
\ No newline at end of file
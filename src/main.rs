@@ -1,25 +1,49 @@
 mod entry;
-use chrono::prelude::*;
+mod manifest;
+mod query;
+mod time;
 
-use std::error::Error;
-use std::{fs, io};
+use std::fmt;
+use std::fs;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Read, Write};
+use std::io::{BufReader, Read, Seek, Write};
 use std::path::{Path, PathBuf};
-use std::collections::VecDeque;
-use tracing_subscriber::{prelude::*, layer::SubscriberExt, util::SubscriberInitExt};
-use clap::{Args, Parser, Subcommand};
+use std::str::FromStr;
+use std::collections::{HashMap, VecDeque};
+use std::time::SystemTime;
+use tracing_subscriber::{prelude::*, util::SubscriberInitExt};
+use clap::{Parser, Subcommand, ValueEnum};
 use tracing_subscriber::filter::LevelFilter;
-use utmp;
-use utmp::UT_RECORDSIZE;
-use crate::entry::UtmpEntry;
-use tabled::{Tabled, Table, Style, Modify, Padding, object::Rows, Alignment};
+use utmp::Layout;
+use utmp::file::UtmpFile;
+use utmp::reader::UtmpReader;
+use utmp::recover::{Scanned, Scanner};
+use utmp::utx::UtxReader;
+use utmp::utmp::Utmp;
+use crate::manifest::{FileState, Manifest};
+use crate::query::Query;
+use crate::time::{Span, When, Zone};
+use crate::entry::{AuditEntry, BootEntry, CarvedEntry, ExportEntry, FindingEntry, SessionEntry, StatsEntry, UtmpEntry};
+use tabled::{Table, Style, Modify, object::Rows, Alignment, builder::Builder};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Print a line about the run. It goes to stderr when stdout carries data, such as JSON, which stays parseable that way.
+macro_rules! note {
+    ($cli:expr, $($arg:tt)*) => {
+        if $cli.notes_to_stdout() { println!($($arg)*) } else { eprintln!($($arg)*) }
+    };
+}
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Cli {
+    /// What to do with the target file(s), `list` by default.
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Specify the target file.
-    #[clap(short = 't', value_parser, value_name = "file",
+    #[clap(short = 't', value_parser, value_name = "file", global = true,
     next_display_order = 1,
     default_values_os_t = vec ! [
     PathBuf::from("/run/utmp"),
@@ -35,20 +59,222 @@ struct Cli {
     /// search the condition to filter the records.
     ///
     /// UnionCode is the base62 of timestamps, it can consider as unique.
-    #[clap(short = 's', value_name = "Pid | Hostname | UnionCode")]
+    #[clap(short = 's', value_name = "Pid | Hostname | UnionCode", global = true)]
     condition: Option<Vec<String>>,
 
+    /// Filter the rows of any command with an expression, then order and limit them, e.g.
+    /// `user = root AND ipaddr IN 10.0.0.0/8 AND time >= 2022-08-01 ORDER BY time DESC LIMIT 10`.
+    ///
+    /// Fields are the names of `--columns`, plus `user`, `host`, `ip` and `type`, such as USER_PROCESS.
+    /// It supports `= != < <= > >=`, `~` and `!~` for regexes, `IN` for CIDR blocks or lists, `BETWEEN ... AND ...`,
+    /// and AND, OR, NOT with parentheses. Quote values with spaces or symbols. -c applies first, so use -c 0 to order all records.
+    /// For `stats` and `export` the filter applies to the records, and ORDER BY to the rows printed.
+    /// It only reads, and cannot be used with -D.
+    #[clap(long = "where", value_parser, value_name = "expression", global = true)]
+    query: Option<Query>,
+
+    /// Only show records from this time on, and sessions and boot periods that last until then.
+    ///
+    /// Such as `2022-08-01`, `"2022-08-01 08:00"`, `2022-08-01T08:00:00+08:00`, `today`, `yesterday`, `now` or `2h ago`,
+    /// times without an offset being in the --tz zone. It does not apply to `audit` and `check`.
+    #[clap(long = "since", value_parser, value_name = "time", global = true)]
+    since: Option<When>,
+
+    /// Only show records before this time, and sessions and boot periods that start before it. See --since.
+    #[clap(long = "until", value_parser, value_name = "time", global = true)]
+    until: Option<When>,
+
+    /// Show times in UTC, local time or an IANA zone such as Asia/Shanghai, each with its offset.
+    #[clap(long = "tz", value_parser, value_name = "zone", default_value_t = Zone::Utc, global = true)]
+    tz: Zone,
+
+    /// --since and --until as instants, resolved once at the start.
+    #[clap(skip)]
+    span: Span,
+
     /// Specify the record count to aim in the target file.
     ///
-    /// if set 0, it means all records.
-    #[clap(short = 'c', value_name = "number", default_value_t = 5)]
+    /// if set 0, it means all records. `stats` counts all records and shows this many rows per field, `export` writes all records.
+    #[clap(short = 'c', value_name = "number", default_value_t = 5, global = true)]
     count: u32,
 
-    /// DELETE the matched records in the target file(s).
+    /// Specify how to print the rows of the command.
+    ///
+    /// `json` prints one array per target file, `ndjson` one object per line, for `jq` and log pipelines.
+    /// `csv` and `tsv` print a header line and one line per row, for spreadsheets.
+    /// Everything but the table then goes to stderr.
+    #[clap(long = "output", value_enum, value_name = "format", default_value_t = Output::Table, global = true)]
+    output: Output,
+
+    /// Choose the columns to print, and their order, by field name, e.g. `offset,typeid,username,time`.
+    ///
+    /// The names are the keys of the JSON output. Records also have `typeid`, `terminalid`, `terminationstatus`,
+    /// `exitstatus` and `offset`, the byte offset of the record in the file, which the table leaves out by default.
+    #[clap(long = "columns", value_name = "column", value_delimiter = ',', global = true)]
+    columns: Option<Vec<String>>,
+
+    /// Keep reading past damaged regions of the target file(s).
+    ///
+    /// Implausible records are skipped up to the next plausible record, and every skipped byte range is reported.
+    /// It cannot be used with -D.
+    #[clap(long = "recover", action, global = true)]
+    recover: bool,
+
+    /// Specify the record layout of the target file(s), such as glibc-be for a wtmp from s390x or ppc64, musl for Alpine, glibc64 for aarch64,
+    /// libc5 for old systems, bsd or macos for BSD and Mac OS X files, utx for a FreeBSD utx.log,
+    /// or sqlite for a wtmpdb or lastlog2 database (needs the `sqlite` feature).
+    ///
+    /// By default it is detected from the first records of each file, and `carve` tries every layout.
+    #[clap(long = "layout", value_parser, value_name = "layout", global = true)]
+    layout: Option<Source>,
+
+    /// Write an evidence manifest to this file: the SHA-256 and metadata of every target file taken before and after
+    /// the analysis, with the tool version and command line.
+    #[clap(long = "manifest", value_parser, value_name = "file", global = true)]
+    manifest: Option<PathBuf>,
+}
+
+impl Cli {
+    /// The command to run, `list` when none is given.
+    fn command(&self) -> Command {
+        self.command.clone().unwrap_or(Command::List { delete: false })
+    }
+
+    /// Whether `list -D` was given.
+    fn delete(&self) -> bool {
+        matches!(self.command, Some(Command::List { delete: true }))
+    }
+
+    /// Whether lines about the run go to stdout, which only carries a table then.
+    fn notes_to_stdout(&self) -> bool {
+        self.output == Output::Table && !matches!(self.command, Some(Command::Export { .. }))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Subcommand)]
+enum Command {
+    /// List the raw records, newest first. This is the default.
+    List {
+        /// DELETE the matched records in the target file(s).
+        ///
+        /// if the flag does set, it will just print out the records match the conditions.
+        /// Only built with the `write` feature.
+        #[cfg_attr(feature = "write", clap(short = 'D', action))]
+        #[cfg_attr(not(feature = "write"), clap(skip))]
+        delete: bool,
+    },
+    /// Pair logins with logouts like `last` does, newest first.
+    ///
+    /// The count and -s apply to the login records.
+    Sessions,
+    /// List boot periods, runlevel changes and unclean shutdowns like `last -x`, newest first.
     ///
-    /// if the flag does set, it will just print out the records match the conditions.
-    #[clap(short = 'D', action)]
-    delete: bool,
+    /// The count applies to the boots.
+    Reboots,
+    /// Report every structural problem with its byte offset, in file order, and exit with 1 if any file has one.
+    Audit,
+    /// Report records that parse fine but are logically inconsistent, such as likely tampering.
+    Check,
+    /// Scan any binary input, such as a disk image or memory dump, for records at any offset.
+    Carve {
+        /// The lowest confidence, from 0 to 1, of a carved record.
+        #[clap(long = "min-confidence", value_name = "score", default_value_t = 0.75)]
+        min_confidence: f64,
+    },
+    /// Count the records by type, user, host, terminal and day, with the first and last time of each.
+    ///
+    /// The most frequent come first, and the newest days.
+    Stats {
+        /// Choose what to count the records by.
+        #[clap(long = "by", value_enum, value_name = "field", value_delimiter = ',',
+        default_values_t = [Group::Type, Group::User, Group::Host, Group::Terminal, Group::Day])]
+        by: Vec<Group>,
+    },
+    /// Write every matching record of the target file(s), oldest first, with all fields and the file it came from.
+    Export {
+        /// The format to write.
+        #[clap(long = "format", value_enum, value_name = "format", default_value_t = Output::Csv)]
+        format: Output,
+        /// Write to this file instead of stdout.
+        #[clap(short = 'o', long = "out", value_parser, value_name = "file")]
+        out: Option<PathBuf>,
+    },
+}
+
+/// What `stats` counts the records by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Group {
+    Type,
+    User,
+    Host,
+    Terminal,
+    /// The date in the --tz zone.
+    Day,
+}
+
+/// How the records of a target file are stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Source {
+    /// Records of the same size, one after another.
+    Fixed(Layout),
+    /// FreeBSD utx.log, records with a length in front.
+    Utx,
+    /// util-linux wtmpdb or lastlog2, an SQLite database.
+    Database,
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("utx") {
+            return Ok(Source::Utx);
+        }
+        if s.eq_ignore_ascii_case("sqlite") {
+            return Ok(Source::Database);
+        }
+        s.parse().map(Source::Fixed).map_err(|e| format!("{}, or utx or sqlite", e))
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Fixed(layout) => layout.fmt(f),
+            Source::Utx => f.write_str("utx"),
+            Source::Database => f.write_str("sqlite"),
+        }
+    }
+}
+
+impl Source {
+    /// Detect how the file is stored, from its start.
+    fn detect(f: &mut File) -> std::io::Result<Source> {
+        let mut magic = [0u8; 16];
+        let filled = f.read(&mut magic)?;
+        f.rewind()?;
+        if magic[..filled] == utmp::SQLITE_MAGIC[..] {
+            return Ok(Source::Database);
+        }
+        if utmp::utx::detect_reader(f)? {
+            return Ok(Source::Utx);
+        }
+        Layout::detect_reader(f).map(Source::Fixed)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Output {
+    /// A markdown table.
+    Table,
+    /// A JSON array.
+    Json,
+    /// One JSON object per line.
+    Ndjson,
+    /// Comma-separated values.
+    Csv,
+    /// Tab-separated values.
+    Tsv,
 }
 
 
@@ -57,7 +283,7 @@ fn main() {
         // .with(tracing_subscriber::EnvFilter::new(
         //     std::env::var("XTRACE_LOG").unwrap_or_else(|_| "XTRACE_LOG=warn".into()),
         // ))
-        .with(tracing_subscriber::fmt::layer().with_filter(LevelFilter::WARN))
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr).with_filter(LevelFilter::WARN))
         .init();
 
     // tracing::error!("tracing::error test");
@@ -69,159 +295,874 @@ fn main() {
     let cli: Cli = Cli::parse();
     tracing::debug!("Parsed command args: {:#?}",cli);
 
+    // 供cron等脚本判断：审计发现问题或无法审计时以1退出。
+    if run(cli) {
+        std::process::exit(1);
+    }
+}
+
+/// Run the command on every target file, true if `audit` found problems or could not audit a target.
+fn run(mut cli: Cli) -> bool {
+    let now = chrono::Utc::now();
+    for (when, end, flag) in [(&cli.since, &mut cli.span.since, "--since"), (&cli.until, &mut cli.span.until, "--until")] {
+        if let Some(when) = when {
+            *end = when.resolve(cli.tz, now);
+            if end.is_none() {
+                // 例如夏令时开始时跳过的时间
+                tracing::error!("The {} time does not exist in {}.   Quiting!", flag, cli.tz);
+                return false;
+            }
+        }
+    }
+
+    if cli.delete() && cli.query.is_some() {
+        tracing::error!("--where only works for reading, it can not be used with -D.   Quiting!");
+        return false;
+    }
+    if cli.delete() && cli.recover {
+        tracing::error!("-D can not be used with --recover.   Quiting!");
+        return false;
+    }
+    if matches!(cli.command, Some(Command::Carve { .. })) && matches!(cli.layout, Some(Source::Utx | Source::Database)) {
+        tracing::error!("Only fixed-size records can be carved.   Quiting!");
+        return false;
+    }
+
     // check target files.
-    let existsfile = cli.targetfile.into_iter().filter(|f| f.exists()).collect::<Vec<_>>();
+    let existsfile = cli.targetfile.iter().filter(|f| f.exists()).collect::<Vec<_>>();
 
     // tracing::debug!("current dir: {:?}",std::env::current_dir());
     // tracing::debug!("testing fils exists:::: {:?}", PathBuf::from("../files4test/utmp").exists());
 
+    // 审计时缺失或无法读取的目标文件也算问题，不能当作正常
+    let auditing = cli.command() == Command::Audit;
+    if auditing {
+        for missing in cli.targetfile.iter().filter(|f| !f.exists()) {
+            report(missing, "the target file does not exist.");
+        }
+    }
     if existsfile.is_empty() {
         tracing::error!("The target file(s) no exists.   Quiting!");
-        return;
+        return auditing;
     }
-    println!("Target Files: {:?}\nFilter Conditions: {:?}\nMax Count: {}"
+    // 导出文件和清单文件不能覆盖目标文件
+    let export = match &cli.command {
+        Some(Command::Export { out, .. }) => out.as_ref(),
+        _ => None,
+    };
+    for (what, out) in [("export", export), ("manifest", cli.manifest.as_ref())] {
+        if let Some(Ok(out)) = out.map(|out| out.canonicalize()) {
+            if existsfile.iter().any(|f| f.canonicalize().is_ok_and(|f| f == out)) {
+                tracing::error!("The {} file {} is one of the target files.   Quiting!", what, out.display());
+                return false;
+            }
+        }
+    }
+    note!(cli, "Target Files: {:?}\nFilter Conditions: {:?}\nMax Count: {}"
         ,existsfile
-        ,&cli.condition.clone().unwrap_or(Vec::new())
+        ,&cli.condition.clone().unwrap_or_default()
         , if cli.count ==0 { "All".to_string()} else { cli.count.to_string()});
+    note!(cli, "Mode: {}", mode(&cli));
+    // 分析前先对全部目标文件取哈希
+    let mut manifest = cli.manifest.as_ref().map(|_| Manifest::new(mode(&cli)));
+    if let Some(manifest) = &mut manifest {
+        for target_file in &existsfile {
+            manifest.before(target_file, open_target(target_file).and_then(FileState::read));
+        }
+    }
+    if let Some(query) = &cli.query {
+        note!(cli, "Query: {}", query);
+    }
+    if !cli.span.is_all() {
+        let show = |end: Option<SystemTime>| end.and_then(|t| cli.tz.localize(t)).map(|t| t.to_string()).unwrap_or_default();
+        note!(cli, "Time Range: {} .. {}", show(cli.span.since), show(cli.span.until));
+    }
 
+    let mut issues_found = auditing && existsfile.len() < cli.targetfile.len();
+    let mut exported = Vec::new();
     // 遍历目标文件
     for target_file in existsfile {
-        println!("\n[ Targeting on {} ]", target_file.to_string_lossy());
+        note!(cli, "\n[ Targeting on {} ]", target_file.to_string_lossy());
+        if let Some(sha256) = manifest.as_ref().and_then(|m| m.sha256(target_file)) {
+            note!(cli, "SHA-256: {}", sha256);
+        }
 
-        let target_file_lenght = fs::metadata(&target_file).unwrap().len();
+        let target_file_lenght = match fs::metadata(target_file) {
+            Ok(metadata) => metadata.len(),
+            Err(e) => {
+                report(target_file, e);
+                issues_found |= auditing;
+                continue;
+            }
+        };
 
-        if target_file_lenght > 384 * 5000 {
-            tracing::warn!("Caution!!! The target file is too large. ({} bytes)", target_file_lenght);
-        } else if target_file_lenght > 384 * 500 {
-            tracing::warn!("Caution! The target file is a bit large. ({} bytes)", target_file_lenght);
-        }
-        if target_file_lenght % (UT_RECORDSIZE as u64) > 0 {
-            println!("Caution! This file may not be a valid utmp file due to inappropriate file size.");
-        } else {
-            println!("Estimated amount of records in the file (by file size): {:5}\nThe Matched Records: ", target_file_lenght / (UT_RECORDSIZE as u64));
+        // 只有删除时才需要把整个文件读入内存。
+        if cli.delete() {
+            if target_file_lenght > 384 * 5000 {
+                tracing::warn!("Caution!!! The target file is too large. ({} bytes)", target_file_lenght);
+            } else if target_file_lenght > 384 * 500 {
+                tracing::warn!("Caution! The target file is a bit large. ({} bytes)", target_file_lenght);
+            }
         }
-
         // let f = File::open(&target_file).unwrap();
-        let f;
-        match File::open(&target_file) {
-            Ok(fhandle) => f = fhandle,
+        let mut f = match open_target(target_file) {
+            Ok(fhandle) => fhandle,
             Err(e) => {
-                tracing::error!("Write File FAILED. | {}",e.to_string());
+                report(target_file, e);
+                issues_found |= auditing;
                 continue;
             },
+        };
+
+        if let Command::Carve { min_confidence } = cli.command() {
+            note!(cli, "Carved Records: ");
+            carve_file(target_file, BufReader::new(f), &cli, min_confidence);
+            continue;
         }
-        let mut reader = BufReader::new(f);
-        let mut utmp_data = Vec::new();
-        reader.read_to_end(&mut utmp_data).unwrap();
-
-        // println!("utmp_data: {:?}",utmp_data);
-        // let (_, utmp_items) = utmp::take_all_records(&utmp_data).ok().unwrap();
-        let (_, utmp_items_with_original_data) = utmp::take_all_records_with_original_data(&utmp_data).ok().unwrap();
-
-
-        // let mut utmpentries_with_postion: Vec<(u32,UtmpEntry)> = Vec::new();
-        let mut utmpentries_with_postion: VecDeque<(u32,UtmpEntry)> = VecDeque::new();
-        let mut utmp_data_with_remove_marks: Vec<(bool, Vec<u8>)> = Vec::new();    // bool用于标注每个utmp数据段是否保留。
-        for (index, (original_data, utmp_item)) in utmp_items_with_original_data.into_iter().enumerate() {
-
-            match UtmpEntry::try_from(utmp_item) {
-                Ok(utmp_entry) => {
-                    match &cli.condition {
-                        Some(condition_vec) => if ((&utmp_entry).pid != None && condition_vec.contains(&utmp_entry.pid.unwrap_or(0).to_string()))
-                            || ((&utmp_entry).hostname != None && condition_vec.contains(&utmp_entry.hostname.clone().unwrap_or(String::new())))
-                            || ((&utmp_entry).unioncode.len() > 0 && condition_vec.contains(&utmp_entry.unioncode))
-                        {
-                            // match the conditions
-                            // utmpentries.push(utmp_entry);
-                            utmp_data_with_remove_marks.push((false, original_data));
-                            utmpentries_with_postion.push_front((index as u32, utmp_entry));
-
-                            // 数据条目超出限制后，pop旧条目，并修改原始数据数组的标记。
-                            if (utmpentries_with_postion.len() > cli.count as usize && cli.count != 0){
-                                let (original_position, _) = utmpentries_with_postion.pop_back().unwrap();
-                                utmp_data_with_remove_marks[original_position as usize] = (true, utmp_data_with_remove_marks[original_position as usize].1.clone());
-                            }
-                        } else{
-                            // 对于不满足条件的条目，直接记录。
-                            utmp_data_with_remove_marks.push((true, original_data));
-                        },
-                        None => {
-                            if (utmpentries_with_postion.len() < cli.count as usize || cli.count == 0) {
-                                utmp_data_with_remove_marks.push((false, original_data));
-                                utmpentries_with_postion.push_front((index as u32, utmp_entry));
-                            } else{
-                                utmp_data_with_remove_marks.push((false, original_data));
-                                utmpentries_with_postion.push_front((index as u32, utmp_entry));
-
-                                let (original_position, _) = utmpentries_with_postion.pop_back().unwrap();
-                                utmp_data_with_remove_marks[original_position as usize] = (true, utmp_data_with_remove_marks[original_position as usize].1.clone());
 
-                            }
-                        }
-                        _ => {}
-                    };
-                    // println!("{:?}", utmpentries_with_postion.iter().map(|s|s.1.time).collect::<Vec<Option<NaiveDateTime>>>());
-                }
+        // 未指定布局时，按前几条记录的合理性判断。
+        let source = match cli.layout {
+            Some(source) => source,
+            None => match Source::detect(&mut f) {
+                Ok(source) => source,
                 Err(e) => {
-                    tracing::error!("it sames not well-format utmp data.");
-                    break;
+                    report(target_file, e);
+                    issues_found |= auditing;
+                    continue;
+                }
+            },
+        };
+        note!(cli, "Layout: {}{}", source, if cli.layout.is_none() { " (detected)" } else { "" });
+        let layout = match source {
+            Source::Fixed(layout) => layout,
+            // utx.log和数据库的记录不定长，无法按位置读取、扫描或审计。
+            Source::Utx | Source::Database => {
+                if cli.delete() {
+                    report(target_file, format_args!("-D is not supported for {}, the file is left untouched.", source));
+                    continue;
+                }
+                if cli.recover {
+                    tracing::warn!("{} | --recover is not supported for {}, reading it as is.", target_file.display(), source);
+                }
+                if source == Source::Utx {
+                    note!(cli, "The Matched Records: ");
+                    issues_found |= present(target_file, source, UtxReader::new(BufReader::new(f)).map(|r| r.map(|utmp| (None, utmp))), &cli, &mut exported);
+                } else if let Some(records) = database_records(target_file, &cli) {
+                    note!(cli, "The Matched Records: ");
+                    issues_found |= present(target_file, source, records.into_iter().map(|utmp| Ok((None, utmp))), &cli, &mut exported);
+                } else {
+                    issues_found |= auditing;
                 }
+                continue;
+            }
+        };
+        let record_size = layout.record_size() as u64;
+        if target_file_lenght % record_size > 0 {
+            note!(cli, "Caution! This file may not be a valid utmp file due to inappropriate file size.");
+        } else {
+            note!(cli, "Estimated amount of records in the file (by file size): {:5}\nThe Matched Records: ", target_file_lenght / record_size);
+        }
+
+        // 只看最后几条记录时，直接从文件末尾按位置读取。
+        if cli.command() == (Command::List { delete: false }) && !cli.recover && cli.condition.is_none() && cli.query.is_none() && cli.count != 0 {
+            match UtmpFile::with_layout(f, layout) {
+                Ok(utmp_file) => tail_records(target_file, &utmp_file, cli.count as usize, &cli),
+                Err(e) => report(target_file, e),
+            }
+            continue;
+        }
+        let reader = BufReader::new(f);
+
+        match cli.command() {
+            Command::List { .. } => list_records(target_file, source, records(target_file, reader, layout, cli.recover), &cli),
+            Command::Sessions => list_sessions(target_file, records(target_file, reader, layout, cli.recover), &cli),
+            Command::Reboots => list_reboots(target_file, records(target_file, reader, layout, cli.recover), &cli),
+            Command::Audit => issues_found |= audit_file(target_file, layout, reader, &cli),
+            Command::Check => check_file(target_file, records(target_file, reader, layout, cli.recover), &cli),
+            Command::Stats { by } => stats_file(target_file, records(target_file, reader, layout, cli.recover), &cli, &by),
+            Command::Export { .. } => exported.extend(export_records(target_file, records(target_file, reader, layout, cli.recover), &cli)),
+            // 已在上面雕刻
+            Command::Carve { .. } => {}
+        }
+    }
+
+    // 所有目标文件的记录一起导出
+    if let Command::Export { format, out } = cli.command() {
+        write_export(exported, format, out.as_deref(), &cli);
+    }
+
+    if let (Some(mut manifest), Some(path)) = (manifest, &cli.manifest) {
+        manifest.finish(|target_file| open_target(target_file).and_then(FileState::read));
+        write_manifest(&manifest, path, &cli);
+    }
+
+    issues_found
+}
+
+/// Run the command on records of a source without fixed-size records, audit does not apply to them.
+///
+/// Returns true if the file could not be audited, so it does not count as clean.
+fn present<I>(target_file: &Path, source: Source, records: I, cli: &Cli, exported: &mut Vec<ExportEntry>) -> bool
+    where I: Iterator<Item=Result<Located, utmp::Error>>
+{
+    if cli.command() == Command::Audit {
+        report(target_file, format_args!("audit is not supported for {}.", source));
+        return true;
+    }
+    match cli.command() {
+        Command::List { .. } => list_records(target_file, source, records, cli),
+        Command::Sessions => list_sessions(target_file, records, cli),
+        Command::Reboots => list_reboots(target_file, records, cli),
+        // 已在上面处理
+        Command::Audit => {}
+        Command::Check => check_file(target_file, records, cli),
+        Command::Stats { by } => stats_file(target_file, records, cli, &by),
+        Command::Export { .. } => exported.extend(export_records(target_file, records, cli)),
+        Command::Carve { .. } => {}
+    }
+    false
+}
+
+/// The records of a wtmpdb or lastlog2 database, `None` if it could not be read.
+#[cfg(feature = "sqlite")]
+fn database_records(target_file: &Path, cli: &Cli) -> Option<Vec<Utmp>> {
+    let res = utmp::wtmpdb::Database::open(target_file).and_then(|db| {
+        note!(cli, "Database: {:?}", db.kind());
+        db.records()
+    });
+    res.map_err(|e| report(target_file, e)).ok()
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn database_records(target_file: &Path, _cli: &Cli) -> Option<Vec<Utmp>> {
+    report(target_file, "this is an SQLite database, rebuild xtrace with `--features sqlite` to read wtmpdb and lastlog2.");
+    None
+}
+
+/// How the run treats the target files, for the report header.
+fn mode(cli: &Cli) -> &'static str {
+    if cli.delete() {
+        "DELETE, the matched records are removed from the target file(s)"
+    } else if cfg!(feature = "write") {
+        "read-only"
+    } else {
+        "read-only (built without the `write` feature)"
+    }
+}
+
+/// Open a target file read-only, without updating its access time where the system allows.
+///
+/// Even with -D the file is only written back through `fs::write` once it has been read completely.
+fn open_target(target_file: &Path) -> std::io::Result<File> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        // O_NOATIME只允许文件属主或有CAP_FOWNER的用户使用
+        match fs::OpenOptions::new().read(true).custom_flags(libc::O_NOATIME).open(target_file) {
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {}
+            res => return res,
+        }
+    }
+    fs::OpenOptions::new().read(true).open(target_file)
+}
+
+/// Log a problem of one target file, prefixed with its path.
+fn report<E: std::fmt::Display>(target_file: &Path, e: E) {
+    tracing::error!("{} | {}", target_file.display(), e);
+}
+
+/// A record and its byte offset in the file, `None` where records have no fixed place.
+type Located = (Option<u64>, Utmp);
+
+/// The records of a file, in file order.
+///
+/// With `recover` damaged regions are skipped and each skipped byte range is reported,
+/// otherwise the records end at the first error.
+fn records<'a, R: Read + 'a>(target_file: &'a Path, reader: R, layout: Layout, recover: bool) -> Box<dyn Iterator<Item=Result<Located, utmp::Error>> + 'a> {
+    if !recover {
+        let record_size = layout.record_size() as u64;
+        return Box::new(UtmpReader::with_layout(reader, layout)
+            .zip(0u64..)
+            .map(move |(r, index)| r.map(|utmp| (Some(index * record_size), utmp))));
+    }
+    Box::new(Scanner::with_layout(reader, layout).filter_map(move |scanned| match scanned {
+        Ok(Scanned::Record { offset, record }) => Some(Ok((Some(offset), record))),
+        Ok(Scanned::Skipped { range }) => {
+            tracing::warn!("{} | skipped bytes {}..{} ({} bytes)", target_file.display(), range.start, range.end, range.end - range.start);
+            None
+        }
+        Err(e) => Some(Err(e)),
+    }))
+}
+
+/// The records up to the first error, which is reported.
+fn read_records<'a, I>(target_file: &'a Path, records: I) -> impl Iterator<Item=Utmp> + 'a
+    where I: Iterator<Item=Result<Located, utmp::Error>> + 'a
+{
+    records.map_while(move |r| r.map(|(_, utmp)| utmp).map_err(|e| report(target_file, e)).ok())
+}
+
+/// Whether the record passes -s, --since, --until and --where.
+fn selected(utmp_entry: &UtmpEntry, time: Option<SystemTime>, cli: &Cli) -> Result<bool, query::QueryError> {
+    if !cli.span.contains(time) {
+        return Ok(false);
+    }
+    if let Some(condition_vec) = &cli.condition {
+        if !matches_condition(utmp_entry, condition_vec) {
+            return Ok(false);
+        }
+    }
+    match &cli.query {
+        Some(query) => query.matches(utmp_entry),
+        None => Ok(true),
+    }
+}
+
+/// Whether the entry matches any of the `-s` conditions.
+fn matches_condition(utmp_entry: &UtmpEntry, condition_vec: &[String]) -> bool {
+    (utmp_entry.pid.is_some() && condition_vec.contains(&utmp_entry.pid.unwrap_or(0).to_string()))
+        || (utmp_entry.hostname.is_some() && condition_vec.contains(&utmp_entry.hostname.clone().unwrap_or_default()))
+        || (!utmp_entry.unioncode.is_empty() && condition_vec.contains(&utmp_entry.unioncode))
+}
+
+/// The rows that pass `--where`, `None` after reporting a field the rows do not have.
+fn filter_rows<T: Serialize>(target_file: &Path, rows: Vec<T>, cli: &Cli) -> Option<Vec<T>> {
+    let Some(query) = &cli.query else { return Some(rows) };
+    let mut kept = Vec::new();
+    for row in rows {
+        match query.matches(&row) {
+            Ok(true) => kept.push(row),
+            Ok(false) => {}
+            Err(e) => {
+                report(target_file, e);
+                return None;
+            }
+        }
+    }
+    Some(kept)
+}
+
+/// Print the rows of a command in the chosen format and columns, `none` being the table's message for no rows.
+fn print_rows<T: tabled::Tabled + Serialize>(rows: Vec<T>, cli: &Cli, none: &str) {
+    if let Err(e) = write_rows(rows, cli.output, cli, none, &mut std::io::stdout().lock()) {
+        tracing::error!("{}", e);
+    }
+}
+
+/// Write the rows in `output`, with the columns and ORDER BY and LIMIT of `cli`.
+fn write_rows<T: tabled::Tabled + Serialize>(rows: Vec<T>, output: Output, cli: &Cli, none: &str, out: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+    let rows = match &cli.query {
+        Some(query) => query.arrange(rows)?,
+        None => rows,
+    };
+    match (output, &cli.columns) {
+        (Output::Table, _) if rows.is_empty() => writeln!(out, "{}", none)?,
+        (Output::Table, None) => write_table(Table::builder(rows), out)?,
+        (Output::Json, None) => writeln!(out, "{}", serde_json::to_string_pretty(&rows)?)?,
+        (Output::Ndjson, None) => for row in &rows {
+            writeln!(out, "{}", serde_json::to_string(row)?)?;
+        },
+        _ => write_columns(&rows, output, cli, out)?,
+    }
+    Ok(())
+}
+
+/// Print the chosen columns, or all fields for CSV and TSV, taken by name from the JSON form of the rows.
+fn write_columns<T: Serialize>(rows: &[T], output: Output, cli: &Cli, out: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+    let objects = rows.iter()
+        .map(|row| serde_json::to_value(row).and_then(serde_json::from_value::<Map<String, Value>>))
+        .collect::<Result<Vec<_>, _>>()?;
+    let names = match &cli.columns {
+        Some(columns) => columns.clone(),
+        None => objects.first().map(|o| o.keys().cloned().collect()).unwrap_or_default(),
+    };
+    if let Some(first) = objects.first() {
+        if let Some(name) = names.iter().find(|name| !first.contains_key(*name)) {
+            return Err(format!("unknown column {:?}, expected some of {}", name, first.keys().cloned().collect::<Vec<_>>().join(", ")).into());
+        }
+    }
+    let selected = objects.into_iter()
+        .map(|mut o| names.iter().map(|name| (name.clone(), o.remove(name).unwrap_or(Value::Null))).collect::<Map<_, _>>())
+        .collect::<Vec<_>>();
+    // 表格和CSV中字符串不加引号，null为空
+    let cells = |o: &Map<String, Value>| o.values().map(|v| match v {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        v => v.to_string(),
+    }).collect::<Vec<_>>();
+
+    match output {
+        Output::Table => {
+            let mut builder = Builder::default();
+            builder.set_columns(names);
+            for o in &selected {
+                builder.add_record(cells(o));
             }
+            write_table(builder, out)?;
         }
+        Output::Json => writeln!(out, "{}", serde_json::to_string_pretty(&selected)?)?,
+        Output::Ndjson => for o in &selected {
+            writeln!(out, "{}", serde_json::to_string(o)?)?;
+        },
+        Output::Csv | Output::Tsv => {
+            let mut writer = csv::WriterBuilder::new()
+                .delimiter(if output == Output::Csv { b',' } else { b'\t' })
+                .from_writer(out);
+            // 没有行也没有指定列时不知道表头
+            if !names.is_empty() {
+                writer.write_record(&names)?;
+            }
+            for o in &selected {
+                writer.write_record(cells(o))?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
 
-        if utmpentries_with_postion.len() > 0 {
-            // utmpentries_with_postion.reverse();
-            println!("\n{}\n", Table::builder(utmpentries_with_postion.into_iter().map(|t|t.1).collect::<Vec<_>>())
-                .index()
-                // .set_name(Some("INDEX".to_string())).clone()
-                .build()
-                // .with(Modify::new(Rows::single(0)).with(Padding::new(0, 0, 1, 1).set_fill('>', '<', '^', 'V')))
-                .with(Modify::new(Rows::single(0)).with(Alignment::center()))
-                .with(Style::markdown())
-                .to_string()
-            );
-            if cli.delete {
-                // print!("Are you sure to remove above entries from the file? (Yes/No) ");
-                // io::stdout().flush().unwrap();
-                // println!("Original file size: {} bytes.\tNew file size: {} bytes.", utmp_data.len(), save_back_data.len());
-                match fs::write(&target_file,
-                                utmp_data_with_remove_marks.into_iter().
-                                    map(|(mark, data)| if mark { data } else { Vec::new() }).flatten()
-                                    .collect::<Vec<_>>(),
-                ) {
-                    Ok(_) => println!("Complete. The above records have been deleted."),
+fn write_table(builder: Builder, out: &mut dyn Write) -> std::io::Result<()> {
+    writeln!(out, "\n{}\n", builder
+        .index()
+        // .set_name(Some("INDEX".to_string())).clone()
+        .build()
+        // .with(Modify::new(Rows::single(0)).with(Padding::new(0, 0, 1, 1).set_fill('>', '<', '^', 'V')))
+        .with(Modify::new(Rows::single(0)).with(Alignment::center()))
+        .with(Style::markdown())
+    )
+}
+
+fn list_records<I>(target_file: &Path, source: Source, records: I, cli: &Cli)
+    where I: Iterator<Item=Result<Located, utmp::Error>>
+{
+    // let mut utmpentries_with_postion: Vec<(u32,UtmpEntry)> = Vec::new();
+    let mut utmpentries_with_postion: VecDeque<(u32,UtmpEntry)> = VecDeque::new();
+    // bool用于标注每条记录是否保留，只有删除时才需要。写回时按偏移复制原始字节。
+    let mut offsets_with_remove_marks: Vec<(bool, Option<u64>)> = Vec::new();
+    // 读取不完整时不写回
+    #[cfg(feature = "write")]
+    let mut read_completely = true;
+    for (index, utmp_item) in records.enumerate() {
+        let (offset, utmp_item) = match utmp_item {
+            Ok(located) => located,
+            Err(e) => {
+                report(target_file, e);
+                #[cfg(feature = "write")]
+                { read_completely = false; }
+                break;
+            }
+        };
+        let time = utmp_item.timestamp();
+
+        match UtmpEntry::with_zone(utmp_item, cli.tz) {
+            Ok(mut utmp_entry) => {
+                utmp_entry.offset = offset;
+                let matched = match selected(&utmp_entry, time, cli) {
+                    Ok(matched) => matched,
                     Err(e) => {
-                        tracing::error!("Write File FAILED. | {}",e.to_string());
-                        continue;
-                    },
+                        report(target_file, e);
+                        return;
+                    }
+                };
+                if cli.delete() {
+                    // 对于不满足条件的条目，直接记录。
+                    offsets_with_remove_marks.push((!matched, offset));
+                }
+                if matched {
+                    utmpentries_with_postion.push_front((index as u32, utmp_entry));
+
+                    // 数据条目超出限制后，pop旧条目，并修改原始数据数组的标记。
+                    if utmpentries_with_postion.len() > cli.count as usize && cli.count != 0 {
+                        if let Some((original_position, _)) = utmpentries_with_postion.pop_back() {
+                            if cli.delete() {
+                                offsets_with_remove_marks[original_position as usize].0 = true;
+                            }
+                        }
+                    }
                 }
-                // let stdin = io::stdin();
-                // for line in stdin.lock().lines() {
-                //     match line {
-                //         Err(_) => break,    // with ^Z
-                //         Ok(s) => if s.to_lowercase().trim_end().eq("yes") || s.to_lowercase().trim_end().eq("y") {
-                //             match fs::write(&target_file,
-                //                             utmp_data_with_remove_marks.into_iter().
-                //                                 map(|(mark, data)| if mark { data } else { Vec::new() }).flatten()
-                //                                 .collect::<Vec<_>>(),
-                //             ) {
-                //                 Ok(_) => println!("Done."),
-                //                 Err(e) => tracing::error!("Something Wrong. | {}",e.to_string()),
-                //             }
-                //             break;
-                //         } else if s.to_lowercase().trim_end().eq("no") || s.to_lowercase().trim_end().eq("n") {
-                //             break;
-                //         } else {
-                //             print!("Are you sure to remove above entries from the file? (Yes/No) ");
-                //             io::stdout().flush().unwrap();
-                //         }
-                //     }
-                // }
+                // println!("{:?}", utmpentries_with_postion.iter().map(|s|s.1.time).collect::<Vec<Option<NaiveDateTime>>>());
+            }
+            Err(e) => {
+                match source {
+                    Source::Fixed(layout) => report(target_file, format_args!("record {} at offset {}: {}", index, index * layout.record_size(), e)),
+                    Source::Utx | Source::Database => report(target_file, format_args!("record {}: {}", index, e)),
+                }
+                #[cfg(feature = "write")]
+                { read_completely = false; }
+                break;
+            }
+        }
+    }
+
+    if !utmpentries_with_postion.is_empty() {
+        // utmpentries_with_postion.reverse();
+        print_rows(utmpentries_with_postion.into_iter().map(|t|t.1).collect::<Vec<_>>(), cli, "---------  NO MATCHED RECORDS FOUND  ----------");
+        #[cfg(feature = "write")]
+        if cli.delete() {
+            write_back(target_file, source, offsets_with_remove_marks, read_completely, cli);
+        }
+    } else {
+        print_rows(Vec::<UtmpEntry>::new(), cli, "---------  NO MATCHED RECORDS FOUND  ----------");
+    }
+}
+
+/// Write the records that are not marked for deletion back over the target file, if it was read completely.
+///
+/// The kept records are copied byte for byte from the file, fields a layout does not keep are not lost.
+#[cfg(feature = "write")]
+fn write_back(target_file: &Path, source: Source, offsets_with_remove_marks: Vec<(bool, Option<u64>)>, read_completely: bool, cli: &Cli) {
+    if !read_completely {
+        tracing::error!("The file was not read completely, it is left untouched.");
+        return;
+    }
+    let Source::Fixed(layout) = source else {
+        report(target_file, format_args!("-D is not supported for {}, the file is left untouched.", source));
+        return;
+    };
+    let utmp_data = match fs::read(target_file) {
+        Ok(data) => data,
+        Err(e) => {
+            report(target_file, e);
+            return;
+        }
+    };
+    let record_size = layout.record_size();
+    let mut save_back_data = Vec::with_capacity(utmp_data.len());
+    for (keep, offset) in offsets_with_remove_marks {
+        let start = offset.unwrap_or_default() as usize;
+        match utmp_data.get(start..start + record_size) {
+            Some(record) if keep => save_back_data.extend_from_slice(record),
+            Some(_) => {}
+            None => {
+                report(target_file, "the file was changed while reading, it is left untouched.");
+                return;
             }
-        } else {
-            println!("---------  NO MATCHED RECORDS FOUND  ----------");
         }
     }
+    // print!("Are you sure to remove above entries from the file? (Yes/No) ");
+    // io::stdout().flush().unwrap();
+    // println!("Original file size: {} bytes.\tNew file size: {} bytes.", utmp_data.len(), save_back_data.len());
+    match fs::write(target_file, save_back_data) {
+        Ok(_) => note!(cli, "Complete. The above records have been deleted."),
+        Err(e) => report(target_file, e),
+    }
+    // let stdin = io::stdin();
+    // for line in stdin.lock().lines() {
+    //     match line {
+    //         Err(_) => break,    // with ^Z
+    //         Ok(s) => if s.to_lowercase().trim_end().eq("yes") || s.to_lowercase().trim_end().eq("y") {
+    //             match fs::write(&target_file,
+    //                             utmp_data_with_remove_marks.into_iter().
+    //                                 map(|(mark, data)| if mark { data } else { Vec::new() }).flatten()
+    //                                 .collect::<Vec<_>>(),
+    //             ) {
+    //                 Ok(_) => println!("Done."),
+    //                 Err(e) => tracing::error!("Something Wrong. | {}",e.to_string()),
+    //             }
+    //             break;
+    //         } else if s.to_lowercase().trim_end().eq("no") || s.to_lowercase().trim_end().eq("n") {
+    //             break;
+    //         } else {
+    //             print!("Are you sure to remove above entries from the file? (Yes/No) ");
+    //             io::stdout().flush().unwrap();
+    //         }
+    //     }
+    // }
+}
+
+/// Print the last `count` records, newest first, reading only those from the end of the file.
+///
+/// With --since or --until the records in the range are found by binary search, which assumes the file is in time order.
+fn tail_records(target_file: &Path, utmp_file: &UtmpFile, count: usize, cli: &Cli) {
+    let record_size = utmp_file.layout().record_size() as u64;
+    let bound = |end: Option<SystemTime>, default: usize| match end {
+        Some(time) => utmp_file.partition_point_by_time(time),
+        None => Ok(default),
+    };
+    let range = match (bound(cli.span.since, 0), bound(cli.span.until, utmp_file.len())) {
+        (Ok(start), Ok(end)) => start..end.max(start),
+        (Err(e), _) | (_, Err(e)) => {
+            report(target_file, e);
+            return;
+        }
+    };
+    let mut utmpentries = Vec::new();
+    for (index, utmp_item) in range.clone().zip(utmp_file.iter_range(range)).rev().take(count) {
+        match utmp_item.map(|utmp| UtmpEntry::with_zone(utmp, cli.tz)) {
+            Ok(Ok(mut utmp_entry)) => {
+                utmp_entry.offset = Some(index as u64 * record_size);
+                utmpentries.push(utmp_entry);
+            }
+            Ok(Err(e)) => {
+                report(target_file, e);
+                break;
+            }
+            Err(e) => {
+                report(target_file, e);
+                break;
+            }
+        }
+    }
+
+    print_rows(utmpentries, cli, "---------  NO MATCHED RECORDS FOUND  ----------");
+}
+
+fn list_sessions<I>(target_file: &Path, records: I, cli: &Cli)
+    where I: Iterator<Item=Result<Located, utmp::Error>>
+{
+    // 与last一致，最近的会话在前。
+    let mut sessions = utmp::session::sessions(read_records(target_file, records));
+    sessions.reverse();
+    let sessionentries = sessions.iter()
+        .filter(|s| cli.span.overlaps(s.start(), s.end_time()))
+        .filter(|s| match &cli.condition {
+            Some(condition_vec) => UtmpEntry::try_from(s.login.clone())
+                .map(|e| matches_condition(&e, condition_vec))
+                .unwrap_or(false),
+            None => true,
+        })
+        .map(|s| SessionEntry::with_zone(s, cli.tz))
+        .collect::<Vec<_>>();
+    let Some(mut sessionentries) = filter_rows(target_file, sessionentries, cli) else { return };
+    if cli.count != 0 {
+        sessionentries.truncate(cli.count as usize);
+    }
+
+    print_rows(sessionentries, cli, "---------  NO MATCHED SESSIONS FOUND  ----------");
+}
+
+fn list_reboots<I>(target_file: &Path, records: I, cli: &Cli)
+    where I: Iterator<Item=Result<Located, utmp::Error>>
+{
+    let bootentries = utmp::boot::boot_history(read_records(target_file, records)).iter()
+        .rev()
+        .filter(|b| cli.span.overlaps(b.start(), b.end_time()))
+        .map(|b| BootEntry::with_zone(b, cli.tz))
+        .collect::<Vec<_>>();
+    let Some(mut bootentries) = filter_rows(target_file, bootentries, cli) else { return };
+    if cli.count != 0 {
+        bootentries.truncate(cli.count as usize);
+    }
+
+    print_rows(bootentries, cli, "---------  NO BOOT RECORDS FOUND  ----------");
+}
+
+/// Print the structural problems of the file, return whether there are any.
+fn audit_file<R: Read>(target_file: &Path, layout: Layout, reader: R, cli: &Cli) -> bool {
+    let issues = match utmp::audit::audit_reader_with(layout, reader) {
+        Ok(issues) => issues,
+        Err(e) => {
+            report(target_file, e);
+            return true;
+        }
+    };
+    if let Some(auditentries) = filter_rows(target_file, issues.iter().map(AuditEntry::from).collect(), cli) {
+        print_rows(auditentries, cli, "---------  NO STRUCTURAL ISSUES FOUND  ----------");
+    }
+    if !issues.is_empty() {
+        note!(cli, "{} structural issue(s) found.", issues.len());
+    }
+    !issues.is_empty()
+}
+
+fn check_file<I>(target_file: &Path, records: I, cli: &Cli)
+    where I: Iterator<Item=Result<Located, utmp::Error>>
+{
+    let utmp_items = read_records(target_file, records).collect::<Vec<_>>();
+
+    let findings = utmp::anomaly::analyze(&utmp_items);
+    if let Some(findingentries) = filter_rows(target_file, findings.iter().map(FindingEntry::from).collect(), cli) {
+        print_rows(findingentries, cli, "---------  NO INCONSISTENCIES FOUND  ----------");
+    }
+    if !findings.is_empty() {
+        note!(cli, "{} finding(s). Records are counted from 0 in file order.", findings.len());
+    }
+}
+
+/// Print how many records there are of each type, user, host, terminal or day.
+fn stats_file<I>(target_file: &Path, records: I, cli: &Cli, by: &[Group])
+    where I: Iterator<Item=Result<Located, utmp::Error>>
+{
+    // 每个分组：值 -> (条数, 最早时间, 最晚时间)
+    let mut tallies = vec![HashMap::<String, (usize, Option<SystemTime>, Option<SystemTime>)>::new(); by.len()];
+    for utmp_item in read_records(target_file, records) {
+        let time = utmp_item.timestamp();
+        let utmp_entry = match UtmpEntry::with_zone(utmp_item.clone(), cli.tz) {
+            Ok(utmp_entry) => utmp_entry,
+            Err(e) => {
+                report(target_file, e);
+                break;
+            }
+        };
+        match selected(&utmp_entry, time, cli) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(e) => {
+                report(target_file, e);
+                return;
+            }
+        }
+        for (group, tally) in by.iter().zip(&mut tallies) {
+            let value = match group {
+                Group::Type => utmp_item.record_type().map(|t| t.to_string()).unwrap_or_default(),
+                Group::User => utmp_item.user(),
+                Group::Host => utmp_item.host(),
+                Group::Terminal => utmp_item.line(),
+                Group::Day => utmp_entry.time.map(|t| t.format("%Y-%m-%d").to_string()).unwrap_or_default(),
+            };
+            if value.is_empty() {
+                continue;
+            }
+            let (count, first, last) = tally.entry(value).or_default();
+            *count += 1;
+            *first = [*first, time].into_iter().flatten().min();
+            *last = [*last, time].into_iter().flatten().max();
+        }
+    }
+
+    let mut statsentries = Vec::new();
+    for (group, tally) in by.iter().zip(tallies) {
+        let mut tally = tally.into_iter().collect::<Vec<_>>();
+        match group {
+            // 日期新的在前，其余多的在前
+            Group::Day => tally.sort_by(|a, b| b.0.cmp(&a.0)),
+            _ => tally.sort_by(|a, b| b.1.0.cmp(&a.1.0).then_with(|| a.0.cmp(&b.0))),
+        }
+        if cli.count != 0 {
+            tally.truncate(cli.count as usize);
+        }
+        statsentries.extend(tally.into_iter().map(|(value, (count, first, last))| StatsEntry {
+            by: group.to_possible_value().map(|v| v.get_name().to_string()).unwrap_or_default(),
+            value,
+            count,
+            first: first.and_then(|t| cli.tz.localize(t)),
+            last: last.and_then(|t| cli.tz.localize(t)),
+        }));
+    }
+
+    print_rows(statsentries, cli, "---------  NO MATCHED RECORDS FOUND  ----------");
+}
+
+/// The records that pass -s, --since, --until and --where, in file order, for `export`.
+fn export_records<I>(target_file: &Path, records: I, cli: &Cli) -> Vec<ExportEntry>
+    where I: Iterator<Item=Result<Located, utmp::Error>>
+{
+    let mut exportentries = Vec::new();
+    for (index, utmp_item) in records.enumerate() {
+        let (offset, utmp_item) = match utmp_item {
+            Ok(located) => located,
+            Err(e) => {
+                report(target_file, e);
+                break;
+            }
+        };
+        let time = utmp_item.timestamp();
+        let mut utmp_entry = match UtmpEntry::with_zone(utmp_item, cli.tz) {
+            Ok(utmp_entry) => utmp_entry,
+            Err(e) => {
+                report(target_file, format_args!("record {}: {}", index, e));
+                break;
+            }
+        };
+        utmp_entry.offset = offset;
+        match selected(&utmp_entry, time, cli) {
+            Ok(true) => exportentries.push(ExportEntry { file: target_file.display().to_string(), entry: utmp_entry }),
+            Ok(false) => {}
+            Err(e) => {
+                report(target_file, e);
+                break;
+            }
+        }
+    }
+    exportentries
+}
+
+/// Write the evidence manifest as pretty JSON.
+fn write_manifest(manifest: &Manifest, path: &Path, cli: &Cli) {
+    let res = File::create(path).and_then(|f| {
+        let mut writer = std::io::BufWriter::new(f);
+        serde_json::to_writer_pretty(&mut writer, manifest)?;
+        writer.flush()
+    });
+    match res {
+        Ok(()) => {
+            let changed = manifest.files.iter().filter(|f| !f.unchanged).count();
+            note!(cli, "Manifest written to {}, {} of {} target file(s) changed or unreadable.", path.display(), changed, manifest.files.len());
+        }
+        Err(e) => report(path, e),
+    }
+}
+
+/// Write the exported records to `out`, or stdout.
+fn write_export(exportentries: Vec<ExportEntry>, format: Output, out: Option<&Path>, cli: &Cli) {
+    let count = exportentries.len();
+    let res = match out {
+        Some(path) => File::create(path)
+            .map_err(|e| e.into())
+            .and_then(|f| {
+                let mut writer = std::io::BufWriter::new(f);
+                write_rows(exportentries, format, cli, "", &mut writer)?;
+                writer.flush().map_err(|e| e.into())
+            }),
+        None => write_rows(exportentries, format, cli, "", &mut std::io::stdout().lock()),
+    };
+    match (res, out) {
+        (Err(e), Some(path)) => report(path, e),
+        (Err(e), None) => tracing::error!("{}", e),
+        (Ok(()), Some(path)) => note!(cli, "Exported {} record(s) to {}.", count, path.display()),
+        (Ok(()), None) => {}
+    }
+}
+
+fn carve_file<R: Read>(target_file: &Path, reader: R, cli: &Cli, min_confidence: f64) {
+    let mut options = utmp::carve::Options { min_confidence, ..Default::default() };
+    if let Some(Source::Fixed(layout)) = cli.layout {
+        options.layouts = vec![layout];
+    }
+    let mut carvedentries = Vec::new();
+    for carved in utmp::carve::Carver::with_options(reader, options) {
+        let carved = match carved {
+            Ok(carved) => carved,
+            Err(e) => {
+                report(target_file, e);
+                break;
+            }
+        };
+        if !cli.span.contains(carved.record.timestamp()) {
+            continue;
+        }
+        let carved_entry = match CarvedEntry::with_zone(&carved, cli.tz) {
+            Ok(carved_entry) => carved_entry,
+            Err(e) => {
+                report(target_file, format_args!("offset {}: {}", carved.offset, e));
+                continue;
+            }
+        };
+        let matched = match &cli.condition {
+            Some(condition_vec) => UtmpEntry::try_from(carved.record)
+                .map(|e| matches_condition(&e, condition_vec))
+                .unwrap_or(false),
+            None => true,
+        };
+        let matched = match (matched, &cli.query) {
+            (true, Some(query)) => match query.matches(&carved_entry) {
+                Ok(matched) => matched,
+                Err(e) => {
+                    report(target_file, e);
+                    return;
+                }
+            },
+            (matched, _) => matched,
+        };
+        if matched {
+            carvedentries.push(carved_entry);
+        }
+        if cli.count != 0 && carvedentries.len() >= cli.count as usize {
+            break;
+        }
+    }
+
+    print_rows(carvedentries, cli, "---------  NO RECORDS CARVED  ----------");
 }
 
 // fn write_to_file<T>(target: T, data: Vec<u8>) -> io::Result<()>
@@ -237,13 +1178,78 @@ fn 测试驱动开发() {
     assert_eq!(42, 42);
 }
 
+#[test]
+fn audit_unreadable_target() {
+    // 缺失的文件和目录都不能当作审计通过
+    assert!(run(Cli::parse_from(["xtrace", "-t/nonexistent/wtmp", "audit"])));
+    let dir = std::env::temp_dir();
+    assert!(run(Cli::parse_from(["xtrace", "-t", dir.to_str().unwrap(), "audit"])));
+    assert!(!run(Cli::parse_from(["xtrace", "-t/nonexistent/wtmp", "list"])));
+}
+
 #[test]
 fn verify_cli() {
-    let cli: Cli = Cli::parse_from(["xtrace", "-t/run/utmp", "-t/var/log/wtmp", "-D", "-c10", "-s127.0.0.1", "-s9527"].into_iter());
+    let cli: Cli = Cli::parse_from(["xtrace", "-t/run/utmp", "-t/var/log/wtmp", "list", "-c10", "-s127.0.0.1", "-s9527"]);
     println!("cli: {:#?}", cli);
-    assert_eq!(cli.delete, true);
+    assert!(!cli.delete());
     assert_eq!(cli.count, 10);
     assert_eq!(cli.condition, Some(vec!["127.0.0.1".to_string(), "9527".to_string()]));
+    assert_eq!(cli.command(), Command::List { delete: false });
+    // 只读构建中没有-D
+    let deleting = Cli::try_parse_from(["xtrace", "-t/var/log/wtmp", "list", "-D"]);
+    if cfg!(feature = "write") {
+        assert!(deleting.unwrap().delete());
+    } else {
+        assert!(deleting.is_err());
+    }
+    // 没有子命令时为list，-D只属于list
+    assert_eq!(Cli::parse_from(["xtrace", "-t/var/log/wtmp"]).command(), Command::List { delete: false });
+    assert!(Cli::try_parse_from(["xtrace", "-D"]).is_err());
+    assert!(Cli::try_parse_from(["xtrace", "sessions", "-D"]).is_err());
+
+    let cli: Cli = Cli::parse_from(["xtrace", "-t/var/log/wtmp", "sessions"]);
+    assert_eq!(cli.command(), Command::Sessions);
+    let cli: Cli = Cli::parse_from(["xtrace", "reboots", "-t/var/log/wtmp"]);
+    assert_eq!(cli.command(), Command::Reboots);
+    assert_eq!(cli.targetfile, vec![PathBuf::from("/var/log/wtmp")]);
+    let cli: Cli = Cli::parse_from(["xtrace", "-t/var/log/wtmp", "audit"]);
+    assert_eq!(cli.command(), Command::Audit);
+    let cli: Cli = Cli::parse_from(["xtrace", "-t/var/log/wtmp", "check"]);
+    assert_eq!(cli.command(), Command::Check);
+    assert!(!cli.recover);
+    let cli: Cli = Cli::parse_from(["xtrace", "-t/var/log/wtmp", "sessions", "--recover"]);
+    assert!(cli.recover);
+    let cli: Cli = Cli::parse_from(["xtrace", "-t/dev/sda1", "carve", "--min-confidence", "0.9"]);
+    assert_eq!(cli.command(), Command::Carve { min_confidence: 0.9 });
+    assert_eq!(cli.layout, None);
+    let cli: Cli = Cli::parse_from(["xtrace", "stats", "--by", "user,host", "-c0"]);
+    assert_eq!(cli.command(), Command::Stats { by: vec![Group::User, Group::Host] });
+    assert_eq!(Cli::parse_from(["xtrace", "stats"]).command(), Command::Stats { by: vec![Group::Type, Group::User, Group::Host, Group::Terminal, Group::Day] });
+    let cli: Cli = Cli::parse_from(["xtrace", "export", "--format", "ndjson", "-o", "wtmp.ndjson"]);
+    assert_eq!(cli.command(), Command::Export { format: Output::Ndjson, out: Some(PathBuf::from("wtmp.ndjson")) });
+    assert!(!cli.notes_to_stdout());
+    let cli: Cli = Cli::parse_from(["xtrace", "-t/var/log/wtmp", "--layout", "glibc-be"]);
+    assert_eq!(cli.layout, Some(Source::Fixed(Layout::new(utmp::Format::Glibc, utmp::Endian::Big))));
+    let cli: Cli = Cli::parse_from(["xtrace", "-t/var/log/utx.log", "--layout", "utx"]);
+    assert_eq!(cli.layout, Some(Source::Utx));
+    let cli: Cli = Cli::parse_from(["xtrace", "-t/var/lib/wtmpdb/wtmp.db", "--layout", "sqlite"]);
+    assert_eq!(cli.layout, Some(Source::Database));
+    assert!(Cli::try_parse_from(["xtrace", "--layout", "solaris"]).is_err());
+    assert_eq!(cli.output, Output::Table);
+    let cli: Cli = Cli::parse_from(["xtrace", "-t/var/log/wtmp", "sessions", "--output", "ndjson"]);
+    assert_eq!(cli.output, Output::Ndjson);
+    let cli: Cli = Cli::parse_from(["xtrace", "-t/var/log/wtmp", "--output", "csv", "--columns", "offset,typeid,username"]);
+    assert_eq!(cli.output, Output::Csv);
+    assert_eq!(cli.columns, Some(vec!["offset".to_string(), "typeid".to_string(), "username".to_string()]));
+    let cli: Cli = Cli::parse_from(["xtrace", "-t/var/log/wtmp", "--where", "user = root AND ip IN 10.0.0.0/8 ORDER BY time DESC LIMIT 3"]);
+    assert_eq!(cli.query.map(|q| q.to_string()), Some("user = root AND ip IN 10.0.0.0/8 ORDER BY time DESC LIMIT 3".to_string()));
+    assert!(Cli::try_parse_from(["xtrace", "--where", "user = "]).is_err());
+    let cli: Cli = Cli::parse_from(["xtrace", "-t/var/log/wtmp", "--since", "2h ago", "--until", "2022-08-01 08:00", "--tz", "Asia/Shanghai"]);
+    assert_eq!(cli.tz, "Asia/Shanghai".parse().unwrap());
+    assert_eq!(cli.until, Some(When::Wall(chrono::NaiveDate::from_ymd_opt(2022, 8, 1).unwrap().and_hms_opt(8, 0, 0).unwrap())));
+    assert!(matches!(cli.since, Some(When::Ago(_))));
+    assert_eq!(Cli::parse_from(["xtrace"]).tz, Zone::Utc);
+    assert!(Cli::try_parse_from(["xtrace", "--tz", "Mars/Olympus"]).is_err());
 }
 
 
@@ -252,7 +1258,7 @@ fn list_utmp_entries() {
     const UTMPDATA: &[u8] = include_bytes!("../files4test/utmp");
 
     let mut utmpentries: Vec<UtmpEntry> = Vec::new();
-    let (_, res2) = utmp::take_all_records(UTMPDATA).ok().unwrap();
+    let res2 = utmp::parse_records(UTMPDATA).unwrap();
 
     for (index, utmp_item) in res2.into_iter().enumerate(){
         let mut utmpentries: Vec<UtmpEntry> = Vec::new();
@@ -287,7 +1293,7 @@ fn check_files() {
     reader.read_to_end(&mut utmp_data).unwrap();
 
     println!("utmp_data: {:?}", utmp_data);
-    let (_, res2) = utmp::take_all_records(&utmp_data).ok().unwrap();
+    let res2 = utmp::parse_records(&utmp_data).unwrap();
     eprintln!("record amount: {}", res2.len())
 }
 
@@ -0,0 +1,159 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// The evidence manifest `--manifest` writes: which bytes were examined, by which build and command line, and whether they changed.
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    pub tool: &'static str,
+    pub version: &'static str,
+    pub command_line: Vec<String>,
+    pub mode: String,
+    pub started: DateTime<Utc>,
+    pub finished: Option<DateTime<Utc>>,
+    pub files: Vec<FileManifest>,
+}
+
+/// One target file, taken before and after the analysis.
+#[derive(Debug, Serialize)]
+pub struct FileManifest {
+    pub path: String,
+    pub before: Option<FileState>,
+    pub after: Option<FileState>,
+    /// Whether the hash and every metadata field are the same after the analysis.
+    pub unchanged: bool,
+    pub errors: Vec<String>,
+}
+
+/// The content hash and metadata of a file at one moment.
+///
+/// Fields the platform does not have are `None`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FileState {
+    pub sha256: String,
+    pub size: u64,
+    pub mtime: Option<DateTime<Utc>>,
+    pub ctime: Option<DateTime<Utc>>,
+    pub dev: Option<u64>,
+    pub inode: Option<u64>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// File type and permission bits in octal, such as 100644.
+    pub mode: Option<String>,
+}
+
+impl FileState {
+    /// Hash a file from its start and take its metadata from the same handle.
+    pub fn read(mut file: File) -> io::Result<FileState> {
+        let metadata = file.metadata()?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut file, &mut hasher)?;
+        let sha256 = format!("{:x}", hasher.finalize());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            Ok(FileState {
+                sha256,
+                size: metadata.size(),
+                mtime: DateTime::from_timestamp(metadata.mtime(), metadata.mtime_nsec() as u32),
+                ctime: DateTime::from_timestamp(metadata.ctime(), metadata.ctime_nsec() as u32),
+                dev: Some(metadata.dev()),
+                inode: Some(metadata.ino()),
+                uid: Some(metadata.uid()),
+                gid: Some(metadata.gid()),
+                mode: Some(format!("{:o}", metadata.mode())),
+            })
+        }
+        #[cfg(not(unix))]
+        Ok(FileState {
+            sha256,
+            size: metadata.len(),
+            mtime: metadata.modified().ok().map(DateTime::from),
+            ctime: None,
+            dev: None,
+            inode: None,
+            uid: None,
+            gid: None,
+            mode: None,
+        })
+    }
+}
+
+impl Manifest {
+    /// Start a manifest for this run, `mode` as the report header states it.
+    pub fn new(mode: &str) -> Manifest {
+        Manifest {
+            tool: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            command_line: std::env::args_os().map(|arg| arg.to_string_lossy().into_owned()).collect(),
+            mode: mode.to_string(),
+            started: Utc::now(),
+            finished: None,
+            files: Vec::new(),
+        }
+    }
+
+    /// Record a target file as it was before the analysis.
+    pub fn before(&mut self, path: &Path, state: io::Result<FileState>) {
+        let mut file = FileManifest { path: path.display().to_string(), before: None, after: None, unchanged: false, errors: Vec::new() };
+        match state {
+            Ok(state) => file.before = Some(state),
+            Err(e) => file.errors.push(format!("before: {}", e)),
+        }
+        self.files.push(file);
+    }
+
+    /// The SHA-256 taken of a target file before the analysis.
+    pub fn sha256(&self, path: &Path) -> Option<&str> {
+        let path = path.display().to_string();
+        self.files.iter().find(|f| f.path == path)?.before.as_ref().map(|s| s.sha256.as_str())
+    }
+
+    /// Take every target file again after the analysis and close the manifest.
+    pub fn finish<F>(&mut self, state: F)
+        where F: Fn(&Path) -> io::Result<FileState>
+    {
+        for file in &mut self.files {
+            match state(Path::new(&file.path)) {
+                Ok(state) => file.after = Some(state),
+                Err(e) => file.errors.push(format!("after: {}", e)),
+            }
+            file.unchanged = file.before.is_some() && file.before == file.after;
+        }
+        self.finished = Some(Utc::now());
+    }
+}
+
+#[test]
+fn test_manifest() {
+    let path = std::env::temp_dir().join(format!("xtrace-manifest-{}", std::process::id()));
+    std::fs::write(&path, b"abc").unwrap();
+    let state = |p: &Path| File::open(p).and_then(FileState::read);
+
+    let mut manifest = Manifest::new("read-only");
+    manifest.before(&path, state(&path));
+    manifest.before(Path::new("/nonexistent/wtmp"), state(Path::new("/nonexistent/wtmp")));
+    assert_eq!(manifest.sha256(&path), Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"));
+    let before = manifest.files[0].before.clone().unwrap();
+    assert_eq!(before.size, 3);
+    #[cfg(unix)]
+    assert!(before.mode.unwrap().starts_with("100"));
+
+    manifest.finish(state);
+    assert!(manifest.files[0].unchanged);
+    assert!(!manifest.files[1].unchanged);
+    assert_eq!(manifest.files[1].errors.len(), 2);
+    assert!(manifest.finished.is_some());
+
+    // 内容改变后哈希不同
+    let mut manifest = Manifest::new("read-only");
+    manifest.before(&path, state(&path));
+    std::fs::write(&path, b"abd").unwrap();
+    manifest.finish(state);
+    assert!(!manifest.files[0].unchanged);
+    std::fs::remove_file(&path).unwrap();
+}
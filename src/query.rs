@@ -0,0 +1,479 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use chrono::{FixedOffset, Offset, Utc};
+use ipnet::IpNet;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use thiserror::Error;
+use crate::time::parse_time;
+
+/// A `--where` expression: a filter on the fields of the rows, then an optional `ORDER BY` and `LIMIT`.
+///
+/// Fields are the keys of the JSON output, plus `user`, `host`, `ip` and `type` (the name of the record type).
+/// ```text
+/// user = root AND type = USER_PROCESS AND ipaddr IN 10.0.0.0/8
+/// NOT host ~ "^192\.168\." AND time BETWEEN 2022-08-01 AND "2022-08-31 23:59:59" ORDER BY time DESC LIMIT 10
+/// ```
+#[derive(Clone, Debug)]
+pub struct Query {
+    text: String,
+    filter: Option<Expr>,
+    order: Vec<(String, bool)>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum QueryError {
+    #[error("invalid query: {0}")]
+    Syntax(String),
+
+    #[error("invalid regex {0:?}: {1}")]
+    Regex(String, regex::Error),
+
+    #[error("unknown field {0:?}, expected one of {1}")]
+    UnknownField(String, String),
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(String, Op, String),
+    Matches(String, Regex),
+    In(String, Vec<String>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    /// A bare word: field name, keyword or value.
+    Word(String),
+    /// A quoted value, never a keyword.
+    Quoted(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(w) => f.write_str(w),
+            Token::Quoted(q) => write!(f, "{:?}", q),
+            Token::Symbol(s) => f.write_str(s),
+        }
+    }
+}
+
+// 长的符号在前，先匹配"<="再匹配"<"
+const SYMBOLS: [&str; 13] = ["==", "!=", "<>", "<=", ">=", "!~", "=", "<", ">", "~", "(", ")", ","];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, QueryError> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c == '"' || c == '\'' {
+            let end = rest[1..].find(c)
+                .ok_or_else(|| QueryError::Syntax(format!("unterminated quote in {}", rest)))?;
+            tokens.push(Token::Quoted(rest[1..=end].to_string()));
+            rest = &rest[end + 2..];
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else {
+            let end = rest.find(|c: char| c.is_whitespace() || "\"'=!<>~(),".contains(c)).unwrap_or(rest.len());
+            // 例如单独的!，既不是符号也不是词
+            if end == 0 {
+                return Err(QueryError::Syntax(format!("unexpected {:?} in {}", c, rest)));
+            }
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// Whether the keyword comes next.
+    fn at(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    /// Consume the keyword if it comes next.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = self.at(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn symbol(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&self, what: &str) -> QueryError {
+        match self.peek() {
+            Some(token) => QueryError::Syntax(format!("expected {}, found {}", what, token)),
+            None => QueryError::Syntax(format!("expected {}, found the end", what)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.and()?;
+        while self.keyword("OR") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.not()?;
+        while self.keyword("AND") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, QueryError> {
+        if self.keyword("NOT") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        if self.symbol("(") {
+            let expr = self.or()?;
+            if !self.symbol(")") {
+                return Err(self.expect("`)`"));
+            }
+            return Ok(expr);
+        }
+        self.condition()
+    }
+
+    fn condition(&mut self) -> Result<Expr, QueryError> {
+        let field = match self.peek() {
+            Some(Token::Word(w)) if !is_keyword(w) => w.to_lowercase(),
+            _ => return Err(self.expect("a field name")),
+        };
+        self.pos += 1;
+        if self.keyword("BETWEEN") {
+            let low = self.value()?;
+            if !self.keyword("AND") {
+                return Err(self.expect("AND"));
+            }
+            let high = self.value()?;
+            return Ok(Expr::And(Box::new(Expr::Compare(field.clone(), Op::Ge, low)), Box::new(Expr::Compare(field, Op::Le, high))));
+        }
+        if self.keyword("IN") {
+            let mut values = Vec::new();
+            if self.symbol("(") {
+                loop {
+                    values.push(self.value()?);
+                    if self.symbol(")") {
+                        break;
+                    }
+                    if !self.symbol(",") {
+                        return Err(self.expect("`,` or `)`"));
+                    }
+                }
+            } else {
+                values.push(self.value()?);
+            }
+            return Ok(Expr::In(field, values));
+        }
+        let op = match self.next() {
+            Some(Token::Symbol("=" | "==")) => Op::Eq,
+            Some(Token::Symbol("!=" | "<>")) => Op::Ne,
+            Some(Token::Symbol("<")) => Op::Lt,
+            Some(Token::Symbol("<=")) => Op::Le,
+            Some(Token::Symbol(">")) => Op::Gt,
+            Some(Token::Symbol(">=")) => Op::Ge,
+            Some(Token::Symbol(s @ ("~" | "!~"))) => {
+                let pattern = self.value()?;
+                let regex = Regex::new(&pattern).map_err(|e| QueryError::Regex(pattern, e))?;
+                let expr = Expr::Matches(field, regex);
+                return Ok(if s == "~" { expr } else { Expr::Not(Box::new(expr)) });
+            }
+            _ => {
+                self.pos -= 1;
+                return Err(self.expect(&format!("an operator after {}", field)));
+            }
+        };
+        Ok(Expr::Compare(field, op, self.value()?))
+    }
+
+    fn value(&mut self) -> Result<String, QueryError> {
+        match self.peek() {
+            Some(Token::Word(w)) if !is_keyword(w) => {
+                let w = w.clone();
+                self.pos += 1;
+                Ok(w)
+            }
+            Some(Token::Quoted(q)) => {
+                let q = q.clone();
+                self.pos += 1;
+                Ok(q)
+            }
+            _ => Err(self.expect("a value")),
+        }
+    }
+}
+
+fn is_keyword(word: &str) -> bool {
+    ["AND", "OR", "NOT", "BETWEEN", "IN", "ORDER", "BY", "ASC", "DESC", "LIMIT"].iter().any(|k| word.eq_ignore_ascii_case(k))
+}
+
+impl FromStr for Query {
+    type Err = QueryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { tokens: tokenize(s)?, pos: 0 };
+        let mut query = Query { text: s.trim().to_string(), filter: None, order: Vec::new(), limit: None };
+        // 可以只有ORDER BY或LIMIT
+        if parser.peek().is_some() && !parser.at("ORDER") && !parser.at("LIMIT") {
+            query.filter = Some(parser.or()?);
+        }
+        if parser.keyword("ORDER") {
+            if !parser.keyword("BY") {
+                return Err(parser.expect("BY"));
+            }
+            loop {
+                let field = parser.value()?.to_lowercase();
+                let descending = parser.keyword("DESC");
+                if !descending {
+                    parser.keyword("ASC");
+                }
+                query.order.push((field, descending));
+                if !parser.symbol(",") {
+                    break;
+                }
+            }
+        }
+        if parser.keyword("LIMIT") {
+            let limit = parser.value()?;
+            query.limit = Some(limit.parse().map_err(|_| QueryError::Syntax(format!("LIMIT needs a number, found {}", limit)))?);
+        }
+        match parser.peek() {
+            Some(_) => Err(parser.expect("AND, OR, ORDER BY, LIMIT or the end")),
+            None if query.filter.is_none() && query.order.is_empty() && query.limit.is_none() => Err(QueryError::Syntax("empty query".to_string())),
+            None => Ok(query),
+        }
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Query {
+    /// Whether the row passes the filter, an error when it names a field the row does not have.
+    pub fn matches<T: Serialize>(&self, row: &T) -> Result<bool, QueryError> {
+        match &self.filter {
+            Some(filter) => filter.eval(&to_map(row)),
+            None => Ok(true),
+        }
+    }
+
+    /// Apply `ORDER BY` and `LIMIT` to the rows.
+    pub fn arrange<T: Serialize>(&self, rows: Vec<T>) -> Result<Vec<T>, QueryError> {
+        let mut rows = match self.order.is_empty() {
+            true => rows,
+            false => {
+                let mut keyed = rows.into_iter()
+                    .map(|row| {
+                        let map = to_map(&row);
+                        let keys = self.order.iter().map(|(field, _)| lookup(&map, field)).collect::<Result<Vec<_>, _>>()?;
+                        Ok((keys, row))
+                    })
+                    .collect::<Result<Vec<_>, QueryError>>()?;
+                // 稳定排序，相同的键保持原有顺序
+                keyed.sort_by(|(a, _), (b, _)| {
+                    a.iter().zip(b).zip(&self.order)
+                        .map(|((a, b), (_, descending))| if *descending { order(b, a) } else { order(a, b) })
+                        .find(|o| o.is_ne())
+                        .unwrap_or(Ordering::Equal)
+                });
+                keyed.into_iter().map(|(_, row)| row).collect()
+            }
+        };
+        if let Some(limit) = self.limit {
+            rows.truncate(limit);
+        }
+        Ok(rows)
+    }
+}
+
+fn to_map<T: Serialize>(row: &T) -> Map<String, Value> {
+    match serde_json::to_value(row) {
+        Ok(Value::Object(map)) => map,
+        _ => Map::new(),
+    }
+}
+
+/// The value of the field, by its JSON key or a shorter name.
+fn lookup(row: &Map<String, Value>, field: &str) -> Result<Value, QueryError> {
+    if let Some(value) = row.get(field) {
+        return Ok(value.clone());
+    }
+    let alias = match field {
+        "user" => "username",
+        "host" => "hostname",
+        "ip" => "ipaddr",
+        // typestr形如" 7 - USER_PROCESS"，只取名称
+        "type" => return row.get("typestr")
+            .and_then(Value::as_str)
+            .map(|s| Value::String(s.rsplit(" - ").next().unwrap_or(s).to_string()))
+            .ok_or_else(|| unknown(row, field)),
+        _ => return Err(unknown(row, field)),
+    };
+    row.get(alias).cloned().ok_or_else(|| unknown(row, field))
+}
+
+fn unknown(row: &Map<String, Value>, field: &str) -> QueryError {
+    QueryError::UnknownField(field.to_string(), row.keys().cloned().collect::<Vec<_>>().join(", "))
+}
+
+impl Expr {
+    fn eval(&self, row: &Map<String, Value>) -> Result<bool, QueryError> {
+        Ok(match self {
+            Expr::And(a, b) => a.eval(row)? && b.eval(row)?,
+            Expr::Or(a, b) => a.eval(row)? || b.eval(row)?,
+            Expr::Not(a) => !a.eval(row)?,
+            Expr::Compare(field, op, literal) => {
+                let value = lookup(row, field)?;
+                match (op, compare(&value, literal)) {
+                    (Op::Eq, o) => o == Some(Ordering::Equal),
+                    (Op::Ne, o) => o != Some(Ordering::Equal),
+                    // null没有大小
+                    (_, None) => false,
+                    (Op::Lt, Some(o)) => o.is_lt(),
+                    (Op::Le, Some(o)) => o.is_le(),
+                    (Op::Gt, Some(o)) => o.is_gt(),
+                    (Op::Ge, Some(o)) => o.is_ge(),
+                }
+            }
+            Expr::Matches(field, regex) => regex.is_match(&text(&lookup(row, field)?)),
+            Expr::In(field, literals) => {
+                let value = lookup(row, field)?;
+                let addr = text(&value).parse::<IpAddr>().ok();
+                literals.iter().any(|literal| match (addr, literal.parse::<IpNet>()) {
+                    (Some(addr), Ok(net)) => net.contains(&addr),
+                    _ => compare(&value, literal) == Some(Ordering::Equal),
+                })
+            }
+        })
+    }
+}
+
+/// The value as a string, null as empty.
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        v => v.to_string(),
+    }
+}
+
+/// Compare a value with a literal: numbers as numbers, times as times, anything else as strings.
+///
+/// Null only equals the empty string.
+fn compare(value: &Value, literal: &str) -> Option<Ordering> {
+    match value {
+        Value::Null => literal.is_empty().then_some(Ordering::Equal),
+        Value::Number(n) => n.as_f64()?.partial_cmp(&literal.parse::<f64>().ok()?),
+        Value::String(s) => compare_times(s, literal).or_else(|| Some(s.as_str().cmp(literal))),
+        v => Some(v.to_string().as_str().cmp(literal)),
+    }
+}
+
+/// Order for `ORDER BY`: null first, numbers as numbers, anything else as strings.
+fn order(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Less,
+        (_, Value::Null) => Ordering::Greater,
+        (Value::Number(a), Value::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()).unwrap_or(Ordering::Equal),
+        (a, b) => compare_times(&text(a), &text(b)).unwrap_or_else(|| text(a).cmp(&text(b))),
+    }
+}
+
+/// Compare two times as instants, `None` unless both are times.
+///
+/// A time without an offset takes the offset of the other, so it reads as the wall clock the output shows.
+fn compare_times(a: &str, b: &str) -> Option<Ordering> {
+    let (a, b) = (parse_time(a)?, parse_time(b)?);
+    let offset = a.1.or(b.1).unwrap_or(Utc.fix());
+    let instant = |(wall, own): (chrono::NaiveDateTime, Option<FixedOffset>)| wall.and_local_timezone(own.unwrap_or(offset)).single();
+    Some(instant(a)?.cmp(&instant(b)?))
+}
+
+#[test]
+fn test_query() {
+    let rows = [
+        serde_json::json!({"typestr": " 7 - USER_PROCESS", "pid": 1234, "username": "root", "hostname": "10.1.2.3", "time": "2022-08-12T04:05:41.288718", "ipaddr": "10.1.2.3"}),
+        serde_json::json!({"typestr": " 7 - USER_PROCESS", "pid": 99, "username": "opc", "hostname": "192.168.0.5", "time": "2022-08-12T07:01:56.390342", "ipaddr": "192.168.0.5"}),
+        serde_json::json!({"typestr": " 8 - DEAD_PROCESS", "pid": 99, "username": "", "hostname": null, "time": null, "ipaddr": null}),
+    ];
+    let select = |q: &str| {
+        let query: Query = q.parse().unwrap();
+        rows.iter().enumerate().filter(|(_, row)| query.matches(row).unwrap()).map(|(i, _)| i).collect::<Vec<_>>()
+    };
+    assert_eq!(select("user=root"), [0]);
+    assert_eq!(select("type = USER_PROCESS and not user = 'root'"), [1]);
+    assert_eq!(select("ipaddr IN 10.0.0.0/8"), [0]);
+    assert_eq!(select("ip in (192.168.0.0/16, 10.1.2.3)"), [0, 1]);
+    assert_eq!(select("host ~ \"^192\\.168\\.\" OR pid > 1000"), [0, 1]);
+    assert_eq!(select("host !~ ^10\\."), [1, 2]);
+    assert_eq!(select("time BETWEEN 2022-08-12 AND '2022-08-12 05:00'"), [0]);
+    assert_eq!(select("time >= 2022-08-12T05:00"), [1]);
+    assert_eq!(select("(pid = 99 OR user = root) AND NOT time < '2022-08-12 07:00'"), [1, 2]);
+    assert_eq!(select("pid = 99 AND hostname = ''"), [2]);
+    assert_eq!(select("ORDER BY pid"), [0, 1, 2]);
+    // 带偏移的时间按时刻比较，不带偏移的字面值按显示的时区理解
+    let shanghai = [serde_json::json!({"time": "2022-08-12T12:05:41.288718+08:00"})];
+    let query: Query = "time BETWEEN '2022-08-12 12:00' AND 2022-08-12T04:10:00Z".parse().unwrap();
+    assert!(query.matches(&shanghai[0]).unwrap());
+
+    let query: Query = "pid = 99 ORDER BY time DESC, pid LIMIT 1".parse().unwrap();
+    assert_eq!(query.arrange(rows.to_vec()).unwrap(), [rows[1].clone()]);
+    let query: Query = "LIMIT 2".parse().unwrap();
+    assert_eq!(query.arrange(rows.to_vec()).unwrap().len(), 2);
+
+    assert!(matches!("session = 1".parse::<Query>().unwrap().matches(&rows[0]), Err(QueryError::UnknownField(..))));
+    for bad in ["", "user", "user = ", "user = root AND", "(user = root", "user ~ '('", "LIMIT x", "user = root ORDER time", "user = root extra", "host = 'open", "user = !root", "!"] {
+        assert!(bad.parse::<Query>().is_err(), "{}", bad);
+    }
+}
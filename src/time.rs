@@ -0,0 +1,250 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// The time zone `--tz` shows times in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Zone {
+    Utc,
+    /// The zone of this machine, from `TZ` or `/etc/localtime`.
+    Local,
+    /// An IANA zone such as Asia/Shanghai.
+    Named(Tz),
+}
+
+impl FromStr for Zone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("utc") {
+            return Ok(Zone::Utc);
+        }
+        if s.eq_ignore_ascii_case("local") {
+            return Ok(Zone::Local);
+        }
+        s.parse().map(Zone::Named).map_err(|_| format!("unknown time zone {:?}, expected UTC, local or an IANA name such as Asia/Shanghai", s))
+    }
+}
+
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Zone::Utc => f.write_str("UTC"),
+            Zone::Local => f.write_str("local"),
+            Zone::Named(tz) => f.write_str(tz.name()),
+        }
+    }
+}
+
+impl Zone {
+    /// The time in this zone, `None` outside the range chrono can represent.
+    pub fn localize(self, time: SystemTime) -> Option<DateTime<FixedOffset>> {
+        let utc = utc(time)?;
+        let local = match self {
+            Zone::Utc => utc.fixed_offset(),
+            Zone::Local => utc.with_timezone(&Local).fixed_offset(),
+            Zone::Named(tz) => utc.with_timezone(&tz).fixed_offset(),
+        };
+        // 偏移后超出范围的时间无法显示
+        local.naive_utc().checked_add_signed(Duration::seconds(local.offset().local_minus_utc().into()))?;
+        Some(local)
+    }
+
+    /// The instant of a wall-clock time in this zone, the earlier one when the clocks went back,
+    /// `None` when the clocks skipped it.
+    pub fn resolve(self, wall: NaiveDateTime) -> Option<DateTime<Utc>> {
+        match self {
+            Zone::Utc => Some(wall.and_utc()),
+            Zone::Local => Local.from_local_datetime(&wall).earliest().map(|t| t.to_utc()),
+            Zone::Named(tz) => tz.from_local_datetime(&wall).earliest().map(|t| t.to_utc()),
+        }
+    }
+}
+
+/// `SystemTime` as UTC, `None` outside the range chrono can represent.
+///
+/// `DateTime::<Utc>::from(SystemTime)` panics there, and the time comes straight from the file.
+fn utc(time: SystemTime) -> Option<DateTime<Utc>> {
+    let (secs, nsecs) = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => (i64::try_from(d.as_secs()).ok()?, d.subsec_nanos()),
+        Err(e) => {
+            let d = e.duration();
+            let secs = 0i64.checked_sub_unsigned(d.as_secs())?;
+            match d.subsec_nanos() {
+                0 => (secs, 0),
+                nsecs => (secs.checked_sub(1)?, 1_000_000_000 - nsecs),
+            }
+        }
+    };
+    DateTime::from_timestamp(secs, nsecs)
+}
+
+/// A time as written in the output or by hand, with its offset if it has one:
+/// `2022-08-12`, `2022-08-12 04:05`, `2022-08-12T04:05:41.288718`, RFC 3339 such as `2022-08-12T04:05:41+08:00`.
+pub fn parse_time(s: &str) -> Option<(NaiveDateTime, Option<FixedOffset>)> {
+    if let Some(t) = DateTime::parse_from_rfc3339(s).ok()
+        .or_else(|| DateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f %:z").ok())
+    {
+        return Some((t.naive_local(), Some(*t.offset())));
+    }
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()?.and_hms_opt(0, 0, 0))
+        .map(|t| (t, None))
+}
+
+/// A `--since` or `--until` time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum When {
+    /// A time with an offset.
+    Instant(DateTime<FixedOffset>),
+    /// A wall-clock time in the `--tz` zone.
+    Wall(NaiveDateTime),
+    /// Some time before now, e.g. `2h ago`.
+    Ago(Duration),
+    /// Midnight in the `--tz` zone, this many days before today: `today` or `yesterday`.
+    DaysBack(u32),
+}
+
+impl FromStr for When {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.to_lowercase().as_str() {
+            "now" => return Ok(When::Ago(Duration::zero())),
+            "today" => return Ok(When::DaysBack(0)),
+            "yesterday" => return Ok(When::DaysBack(1)),
+            _ => {}
+        }
+        if let Some(ago) = s.strip_suffix("ago") {
+            return parse_duration(ago.trim()).map(When::Ago)
+                .ok_or_else(|| format!("invalid time {:?}, expected a number and a unit such as 2h ago or 3 days ago", s));
+        }
+        match parse_time(s) {
+            Some((t, Some(offset))) => t.and_local_timezone(offset).single().map(When::Instant).ok_or_else(|| format!("invalid time {:?}", s)),
+            Some((t, None)) => Ok(When::Wall(t)),
+            None => Err(format!("invalid time {:?}, expected such as 2022-08-01, \"2022-08-01 08:00\", 2022-08-01T08:00:00+08:00, today, yesterday or 2h ago", s)),
+        }
+    }
+}
+
+/// `90s`, `30 min`, `2h`, `3 days` or `1 week`.
+fn parse_duration(s: &str) -> Option<Duration> {
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let n = s[..digits].parse::<i64>().ok()?;
+    let seconds = match s[digits..].trim() {
+        "s" | "sec" | "secs" | "second" | "seconds" => 1,
+        "m" | "min" | "mins" | "minute" | "minutes" => 60,
+        "h" | "hour" | "hours" => 60 * 60,
+        "d" | "day" | "days" => 24 * 60 * 60,
+        "w" | "week" | "weeks" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    Duration::try_seconds(n.checked_mul(seconds)?)
+}
+
+impl When {
+    /// The instant, with wall-clock times in `zone`. `None` when the clocks of the zone skipped it.
+    pub fn resolve(&self, zone: Zone, now: DateTime<Utc>) -> Option<SystemTime> {
+        let time = match self {
+            When::Instant(t) => t.to_utc(),
+            When::Wall(t) => zone.resolve(*t)?,
+            When::Ago(d) => now.checked_sub_signed(*d)?,
+            When::DaysBack(days) => {
+                let today = zone.localize(now.into())?.date_naive();
+                zone.resolve(today.checked_sub_days(chrono::Days::new((*days).into()))?.and_hms_opt(0, 0, 0)?)?
+            }
+        };
+        Some(time.into())
+    }
+}
+
+/// The times `--since` and `--until` keep, `since <= time < until`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub since: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+}
+
+impl Span {
+    /// Whether neither end is set.
+    pub fn is_all(&self) -> bool {
+        self.since.is_none() && self.until.is_none()
+    }
+
+    /// Whether the time is in the span; an invalid time is only in an unbounded span.
+    pub fn contains(&self, time: Option<SystemTime>) -> bool {
+        match time {
+            Some(time) => self.since.is_none_or(|since| since <= time) && self.until.is_none_or(|until| time < until),
+            None => self.is_all(),
+        }
+    }
+
+    /// Whether a period from `start` to `end`, `None` while it lasts, overlaps the span.
+    pub fn overlaps(&self, start: Option<SystemTime>, end: Option<SystemTime>) -> bool {
+        match start {
+            Some(start) => self.until.is_none_or(|until| start < until) && self.since.is_none_or(|since| end.is_none_or(|end| since <= end)),
+            None => self.is_all(),
+        }
+    }
+}
+
+#[test]
+fn test_zone() {
+    let time = UNIX_EPOCH + std::time::Duration::from_micros(1_660_277_141_288_718);
+    assert_eq!(Zone::Utc.localize(time).unwrap().to_string(), "2022-08-12 04:05:41.288718 +00:00");
+    let shanghai: Zone = "Asia/Shanghai".parse().unwrap();
+    assert_eq!(shanghai.localize(time).unwrap().to_string(), "2022-08-12 12:05:41.288718 +08:00");
+    assert_eq!("utc".parse::<Zone>(), Ok(Zone::Utc));
+    assert_eq!("Local".parse::<Zone>(), Ok(Zone::Local));
+    assert!("Mars/Olympus".parse::<Zone>().is_err());
+    // 超出chrono范围的时间
+    assert_eq!(Zone::Utc.localize(UNIX_EPOCH + std::time::Duration::from_secs(u64::MAX / 2)), None);
+    // UTC还能表示，加上偏移就超出了
+    let last = UNIX_EPOCH + std::time::Duration::from_secs(DateTime::<Utc>::MAX_UTC.timestamp() as u64);
+    assert!(Zone::Utc.localize(last).is_some());
+    assert_eq!("Pacific/Kiritimati".parse::<Zone>().unwrap().localize(last), None);
+
+    // 夏令时：跳过的时间没有对应的时刻，重复的时间取较早的
+    let berlin: Zone = "Europe/Berlin".parse().unwrap();
+    let wall = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+    assert_eq!(berlin.resolve(wall("2022-03-27 02:30")), None);
+    assert_eq!(berlin.resolve(wall("2022-10-30 02:30")).unwrap().to_string(), "2022-10-30 00:30:00 UTC");
+}
+
+#[test]
+fn test_when() {
+    let now = "2022-08-12T04:05:41Z".parse::<DateTime<Utc>>().unwrap();
+    let resolve = |s: &str, zone: &str| DateTime::<Utc>::from(s.parse::<When>().unwrap().resolve(zone.parse().unwrap(), now).unwrap()).to_rfc3339();
+    assert_eq!(resolve("2h ago", "UTC"), "2022-08-12T02:05:41+00:00");
+    assert_eq!(resolve("3 days ago", "UTC"), "2022-08-09T04:05:41+00:00");
+    assert_eq!(resolve("now", "UTC"), "2022-08-12T04:05:41+00:00");
+    assert_eq!(resolve("today", "UTC"), "2022-08-12T00:00:00+00:00");
+    // 上海已是8月12日中午，昨天是8月11日
+    assert_eq!(resolve("yesterday", "Asia/Shanghai"), "2022-08-10T16:00:00+00:00");
+    assert_eq!(resolve("2022-08-01", "Asia/Shanghai"), "2022-07-31T16:00:00+00:00");
+    assert_eq!(resolve("2022-08-01 08:00", "UTC"), "2022-08-01T08:00:00+00:00");
+    assert_eq!(resolve("2022-08-01T08:00:00+08:00", "America/New_York"), "2022-08-01T00:00:00+00:00");
+    for bad in ["", "soon", "2h", "h ago", "2 fortnights ago", "2022-13-01", "99999999999999999 days ago"] {
+        assert!(bad.parse::<When>().is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_span() {
+    let at = |secs: u64| Some(UNIX_EPOCH + std::time::Duration::from_secs(secs));
+    let span = Span { since: at(10), until: at(20) };
+    assert!(span.contains(at(10)) && span.contains(at(19)));
+    assert!(!span.contains(at(20)) && !span.contains(at(9)) && !span.contains(None));
+    assert!(Span::default().contains(None));
+
+    assert!(span.overlaps(at(5), at(10)));
+    assert!(span.overlaps(at(15), None));
+    assert!(span.overlaps(at(0), None));
+    assert!(!span.overlaps(at(0), at(9)));
+    assert!(!span.overlaps(at(20), at(30)));
+    assert!(!span.overlaps(None, at(15)));
+}
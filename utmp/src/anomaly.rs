@@ -0,0 +1,311 @@
+use std::fmt;
+use std::time::{Duration, SystemTime};
+use crate::utmp::Utmp;
+use crate::session::{closes, effective_type};
+use crate::{BOOT_TIME, DEAD_PROCESS, EMPTY, INIT_PROCESS, LOGIN_PROCESS, NEW_TIME, OLD_TIME, SHUTDOWN_TIME, USER_PROCESS};
+
+/// 9999-12-31 23:59:59 UTC, the last second a date is shown for.
+const LATEST: i64 = 253_402_300_799;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Low,
+    Medium,
+    High,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Info => "info",
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+        })
+    }
+}
+
+/// A logical inconsistency found by [`analyze`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FindingKind {
+    /// A record is older than the one before it, without an OLD_TIME/NEW_TIME pair announcing a clock change.
+    TimeWentBackwards { by: Duration },
+    /// A record whose `ut_tv` is no date: before the epoch, after the year 9999, or with `tv_usec` not below one second.
+    InvalidTime { sec: i64, usec: i64 },
+    /// A DEAD_PROCESS record with no open process on its terminal.
+    LogoutWithoutLogin,
+    /// A USER_PROCESS record on a terminal that already has an open login.
+    DuplicateLogin,
+    /// No records at all for a long time within one boot period.
+    Gap { length: Duration },
+    /// A boot period with far fewer records than the one before it.
+    RecordCountDrop { previous: usize, current: usize },
+}
+
+impl fmt::Display for FindingKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FindingKind::TimeWentBackwards { by } => write!(f, "time went backwards by {}s without a clock change", by.as_secs()),
+            FindingKind::InvalidTime { sec, usec } => write!(f, "invalid time of {}s and {}us", sec, usec),
+            FindingKind::LogoutWithoutLogin => write!(f, "logout without a login"),
+            FindingKind::DuplicateLogin => write!(f, "terminal opened twice"),
+            FindingKind::Gap { length } => write!(f, "no records for {}s within a boot period", length.as_secs()),
+            FindingKind::RecordCountDrop { previous, current } => write!(f, "record count dropped from {} to {} between boots", previous, current),
+        }
+    }
+}
+
+/// One inconsistency, its severity and the records that caused it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub kind: FindingKind,
+    /// Indices of the records involved, in the slice passed to [`analyze`].
+    pub records: Vec<usize>,
+}
+
+/// Thresholds of [`analyze_with`].
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Shortest silence within a boot period reported as a gap.
+    pub gap: Duration,
+    /// A boot period with fewer than `previous * drop_ratio` records is reported.
+    pub drop_ratio: f64,
+    /// Boot periods with fewer records than this are not compared.
+    pub drop_min_records: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            gap: Duration::from_secs(7 * 24 * 3600),
+            drop_ratio: 0.1,
+            drop_min_records: 20,
+        }
+    }
+}
+
+/// [`analyze_with`] and the default thresholds.
+pub fn analyze(records: &[Utmp]) -> Vec<Finding> {
+    analyze_with(records, &Options::default())
+}
+
+/// Look for records that parse fine but do not fit together, as left behind by editing a wtmp file.
+///
+/// Records must be in file order. Logouts before the first BOOT_TIME are not checked,
+/// their logins may sit in an older, rotated file.
+///
+/// The findings are sorted by the records involved.
+pub fn analyze_with(records: &[Utmp], options: &Options) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    check_time_order(records, &mut findings);
+    check_logins(records, &mut findings);
+    check_boot_periods(records, options, &mut findings);
+    findings.sort_by(|a, b| a.records.cmp(&b.records));
+    findings
+}
+
+/// The time of a record that holds a valid date, see [`FindingKind::InvalidTime`].
+fn valid_time(record: &Utmp) -> Option<SystemTime> {
+    record.timestamp().filter(|_| (0..=LATEST).contains(&record.ut_time_sec))
+}
+
+fn check_time_order(records: &[Utmp], findings: &mut Vec<Finding>) {
+    // 上一条时间有效的记录
+    let mut previous: Option<(usize, SystemTime)> = None;
+    for (index, record) in records.iter().enumerate() {
+        if record.ut_type == EMPTY {
+            continue;
+        }
+        let Some(time) = valid_time(record) else {
+            findings.push(Finding {
+                severity: Severity::Medium,
+                kind: FindingKind::InvalidTime { sec: record.ut_time_sec, usec: record.ut_time_usec },
+                records: vec![index],
+            });
+            continue;
+        };
+        if let Some((p, previous_time)) = previous {
+            let clock_change = matches!((records[p].ut_type, record.ut_type), (OLD_TIME, NEW_TIME) | (NEW_TIME, OLD_TIME));
+            if let Err(e) = time.duration_since(previous_time) {
+                if !clock_change {
+                    findings.push(Finding {
+                        severity: Severity::High,
+                        kind: FindingKind::TimeWentBackwards { by: e.duration() },
+                        records: vec![p, index],
+                    });
+                }
+            }
+        }
+        previous = Some((index, time));
+    }
+}
+
+fn check_logins(records: &[Utmp], findings: &mut Vec<Finding>) {
+    // 当前打开的进程的记录序号
+    let mut open: Vec<usize> = Vec::new();
+    let mut booted = false;
+    for (index, record) in records.iter().enumerate() {
+        match effective_type(record) {
+            // 没有终端的记录之间不比较终端，由DEAD_PROCESS按ut_id或ut_pid关闭
+            USER_PROCESS | INIT_PROCESS | LOGIN_PROCESS if record.ut_line[0] == 0 => {
+                open.push(index);
+            }
+            USER_PROCESS => {
+                if let Some(&p) = open.iter().find(|&&p| records[p].ut_type == USER_PROCESS && records[p].ut_line == record.ut_line) {
+                    findings.push(Finding { severity: Severity::Medium, kind: FindingKind::DuplicateLogin, records: vec![p, index] });
+                }
+                open.retain(|&p| records[p].ut_line != record.ut_line);
+                open.push(index);
+            }
+            INIT_PROCESS | LOGIN_PROCESS => {
+                open.retain(|&p| records[p].ut_line != record.ut_line);
+                open.push(index);
+            }
+            DEAD_PROCESS => {
+                match open.iter().rposition(|&p| closes(&records[p], record)) {
+                    Some(position) => { open.remove(position); }
+                    None if booted => findings.push(Finding { severity: Severity::Medium, kind: FindingKind::LogoutWithoutLogin, records: vec![index] }),
+                    None => {}
+                }
+            }
+            BOOT_TIME | SHUTDOWN_TIME => {
+                booted = true;
+                open.clear();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn check_boot_periods(records: &[Utmp], options: &Options, findings: &mut Vec<Finding>) {
+    // 每个启动周期： (BOOT_TIME记录序号, 周期内记录数)
+    let mut periods: Vec<(usize, usize)> = Vec::new();
+    let mut last_in_period: Option<usize> = None;
+    for (index, record) in records.iter().enumerate() {
+        if record.ut_type == EMPTY {
+            continue;
+        }
+        let ut_type = effective_type(record);
+        if ut_type == BOOT_TIME {
+            periods.push((index, 0));
+            last_in_period = Some(index);
+        } else if let Some(p) = last_in_period {
+            if let Some(Ok(length)) = valid_time(record).zip(valid_time(&records[p])).map(|(time, p)| time.duration_since(p)) {
+                if length >= options.gap && !matches!(record.ut_type, OLD_TIME | NEW_TIME) {
+                    findings.push(Finding { severity: Severity::Low, kind: FindingKind::Gap { length }, records: vec![p, index] });
+                }
+            }
+            last_in_period = if ut_type == SHUTDOWN_TIME { None } else { Some(index) };
+        }
+        if let Some(period) = periods.last_mut() {
+            period.1 += 1;
+        }
+    }
+
+    // 最后一个周期可能仍在运行，不参与比较。
+    if periods.len() > 2 {
+        for pair in periods[..periods.len() - 1].windows(2) {
+            let ((previous_boot, previous), (boot, current)) = (pair[0], pair[1]);
+            if previous >= options.drop_min_records && (current as f64) < previous as f64 * options.drop_ratio {
+                findings.push(Finding {
+                    severity: Severity::Medium,
+                    kind: FindingKind::RecordCountDrop { previous, current },
+                    records: vec![previous_boot, boot],
+                });
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Record;
+
+    fn record(ut_type: i32, line: &str, sec: i64) -> Utmp {
+        Record::new(ut_type, sec).line(line).build()
+    }
+
+    fn kinds(findings: &[Finding]) -> Vec<(FindingKind, Vec<usize>)> {
+        findings.iter().map(|f| (f.kind.clone(), f.records.clone())).collect()
+    }
+
+    #[test]
+    fn test_analyze_clean() {
+        let records = vec![
+            record(DEAD_PROCESS, "pts/9", 50),
+            record(BOOT_TIME, "~", 100),
+            record(USER_PROCESS, "pts/0", 110),
+            record(OLD_TIME, "", 120),
+            record(NEW_TIME, "", 60),
+            record(DEAD_PROCESS, "pts/0", 70),
+        ];
+        assert_eq!(analyze(&records), vec![]);
+    }
+
+    #[test]
+    fn test_analyze_login_findings() {
+        let records = vec![
+            record(BOOT_TIME, "~", 100),
+            record(USER_PROCESS, "pts/0", 110),
+            record(USER_PROCESS, "pts/0", 120),
+            record(DEAD_PROCESS, "pts/0", 130),
+            record(DEAD_PROCESS, "pts/1", 140),
+            record(USER_PROCESS, "pts/2", 90),
+        ];
+        assert_eq!(kinds(&analyze(&records)), vec![
+            (FindingKind::DuplicateLogin, vec![1, 2]),
+            (FindingKind::LogoutWithoutLogin, vec![4]),
+            (FindingKind::TimeWentBackwards { by: Duration::from_secs(50) }, vec![4, 5]),
+        ]);
+    }
+
+    #[test]
+    fn test_analyze_logins_without_line() {
+        let records = vec![
+            record(BOOT_TIME, "~", 100),
+            Record::new(USER_PROCESS, 110).pid(10).user("alice").build(),
+            Record::new(USER_PROCESS, 120).pid(11).user("bob").build(),
+            Record::new(DEAD_PROCESS, 130).pid(10).build(),
+            Record::new(DEAD_PROCESS, 140).pid(11).build(),
+        ];
+        assert_eq!(analyze(&records), vec![]);
+    }
+
+    #[test]
+    fn test_analyze_invalid_time() {
+        let mut records = vec![
+            record(BOOT_TIME, "~", 100),
+            record(USER_PROCESS, "pts/0", -5),
+            record(USER_PROCESS, "pts/1", 110),
+            record(USER_PROCESS, "pts/2", i64::MAX),
+            record(DEAD_PROCESS, "pts/1", 120),
+        ];
+        records[2].ut_time_usec = 1_000_000;
+        assert_eq!(kinds(&analyze(&records)), vec![
+            (FindingKind::InvalidTime { sec: -5, usec: 0 }, vec![1]),
+            (FindingKind::InvalidTime { sec: 110, usec: 1_000_000 }, vec![2]),
+            (FindingKind::InvalidTime { sec: i64::MAX, usec: 0 }, vec![3]),
+        ]);
+    }
+
+    #[test]
+    fn test_analyze_boot_periods() {
+        let day = 24 * 3600;
+        let mut records = vec![record(BOOT_TIME, "~", 0)];
+        records.extend((1..30).map(|i| record(LOGIN_PROCESS, "tty1", i)));
+        records.push(record(BOOT_TIME, "~", 100));
+        records.push(record(LOGIN_PROCESS, "tty1", 100 + 10 * day));
+        records.push(record(BOOT_TIME, "~", 200 + 10 * day));
+        let res = analyze(&records);
+        assert_eq!(kinds(&res), vec![
+            (FindingKind::RecordCountDrop { previous: 30, current: 2 }, vec![0, 30]),
+            (FindingKind::Gap { length: Duration::from_secs(10 * day as u64) }, vec![30, 31]),
+        ]);
+        assert_eq!(res[0].severity, Severity::Medium);
+        assert_eq!(res[1].severity, Severity::Low);
+    }
+}
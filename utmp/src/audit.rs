@@ -0,0 +1,202 @@
+use std::fmt;
+use std::io::{self, Read};
+use crate::reader::read_block;
+use crate::Layout;
+
+
+/// A structural problem found by [`audit`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IssueKind {
+    /// A whole record of zero bytes.
+    ZeroBlock,
+    /// Bytes after the last complete record.
+    PartialRecord { len: usize },
+    /// `ut_type` outside `EMPTY..=SHUTDOWN_TIME`.
+    InvalidType(i32),
+    /// A string field with control characters or invalid UTF-8 before its terminator.
+    NonPrintable { field: &'static str },
+    /// A string field that fills its whole width without a NUL terminator.
+    Unterminated { field: &'static str },
+    /// Nonzero bytes in the reserved `__unused` field.
+    NonzeroUnused,
+    /// Nonzero bytes in the padding `__ut_pad1` after `ut_type`.
+    NonzeroPadding,
+}
+
+impl fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IssueKind::ZeroBlock => write!(f, "all-zero record"),
+            IssueKind::PartialRecord { len } => write!(f, "partial trailing record ({} bytes)", len),
+            IssueKind::InvalidType(t) => write!(f, "ut_type({}) is invalid", t),
+            IssueKind::NonPrintable { field } => write!(f, "{} contains non-printable bytes", field),
+            IssueKind::Unterminated { field } => write!(f, "{} is not NUL-terminated", field),
+            IssueKind::NonzeroUnused => write!(f, "__unused contains nonzero bytes"),
+            IssueKind::NonzeroPadding => write!(f, "__ut_pad1 contains nonzero bytes"),
+        }
+    }
+}
+
+/// One structural problem and where it is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditIssue {
+    /// Byte offset of the problem in the file: the start of the record or of the offending field.
+    pub offset: u64,
+    /// Index of the record the problem belongs to.
+    pub record: usize,
+    pub kind: IssueKind,
+}
+
+impl fmt::Display for AuditIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "offset {} (record {}): {}", self.offset, self.record, self.kind)
+    }
+}
+
+/// String fields checked by [`audit`]: name, and whether it must be NUL-terminated.
+///
+/// `ut_id` is left out of the terminator check, it is a 4-byte suffix such as "ts/0" with no room for one.
+const STRING_FIELDS: [(&str, bool); 4] = [
+    ("ut_line", true),
+    ("ut_id", false),
+    ("ut_user", true),
+    ("ut_host", true),
+];
+
+/// Walk a utmp/wtmp/btmp file record by record and report every structural problem.
+///
+/// Records with problems are still checked field by field, except all-zero records which are reported once.
+/// The file is read in the native layout.
+pub fn audit(data: &[u8]) -> Vec<AuditIssue> {
+    audit_with(Layout::native(), data)
+}
+
+/// [`audit`] of a file written in `layout`.
+pub fn audit_with(layout: Layout, data: &[u8]) -> Vec<AuditIssue> {
+    let mut issues = Vec::new();
+    let mut chunks = data.chunks_exact(layout.record_size());
+    for (index, block) in chunks.by_ref().enumerate() {
+        audit_block(layout, index, block, &mut issues);
+    }
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        issues.push(partial_record(layout, data.len() / layout.record_size(), rest.len()));
+    }
+    issues
+}
+
+/// [`audit`] over any `Read`, one record in memory at a time.
+pub fn audit_reader<R: Read>(reader: R) -> io::Result<Vec<AuditIssue>> {
+    audit_reader_with(Layout::native(), reader)
+}
+
+/// [`audit_reader`] of a file written in `layout`.
+pub fn audit_reader_with<R: Read>(layout: Layout, mut reader: R) -> io::Result<Vec<AuditIssue>> {
+    let mut issues = Vec::new();
+    let mut block = vec![0u8; layout.record_size()];
+    for index in 0.. {
+        match read_block(&mut reader, &mut block)? {
+            0 => break,
+            len if len == block.len() => audit_block(layout, index, &block, &mut issues),
+            len => {
+                issues.push(partial_record(layout, index, len));
+                break;
+            }
+        }
+    }
+    Ok(issues)
+}
+
+fn partial_record(layout: Layout, index: usize, len: usize) -> AuditIssue {
+    AuditIssue {
+        offset: (index * layout.record_size()) as u64,
+        record: index,
+        kind: IssueKind::PartialRecord { len },
+    }
+}
+
+fn audit_block(layout: Layout, index: usize, block: &[u8], issues: &mut Vec<AuditIssue>) {
+    let base = index * layout.record_size();
+    let mut push = |offset: usize, kind: IssueKind| issues.push(AuditIssue { offset: offset as u64, record: index, kind });
+
+    if block.iter().all(|b| *b == 0) {
+        push(base, IssueKind::ZeroBlock);
+        return;
+    }
+
+    if let Some(ut_type) = layout.peek_type(block) {
+        if !(crate::EMPTY..=crate::SHUTDOWN_TIME).contains(&ut_type) {
+            push(base, IssueKind::InvalidType(ut_type));
+        }
+    }
+    if let Some((offset, size)) = layout.field("__ut_pad1") {
+        if block[offset..offset + size].iter().any(|b| *b != 0) {
+            push(base + offset, IssueKind::NonzeroPadding);
+        }
+    }
+
+    for (field, terminated) in STRING_FIELDS {
+        let (offset, size) = match layout.field(field) {
+            Some(at) => at,
+            None => continue,
+        };
+        let bytes = &block[offset..offset + size];
+        let end = bytes.iter().position(|b| *b == 0);
+        if terminated && end.is_none() {
+            push(base + offset, IssueKind::Unterminated { field });
+        }
+        if !is_printable(&bytes[..end.unwrap_or(size)]) {
+            push(base + offset, IssueKind::NonPrintable { field });
+        }
+    }
+
+    if let Some((offset, size)) = layout.field("__unused") {
+        if block[offset..offset + size].iter().any(|b| *b != 0) {
+            push(base + offset, IssueKind::NonzeroUnused);
+        }
+    }
+}
+
+fn is_printable(bytes: &[u8]) -> bool {
+    match std::str::from_utf8(bytes) {
+        Ok(s) => !s.chars().any(char::is_control),
+        Err(_) => false,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::block;
+    use crate::UT_RECORDSIZE;
+
+    #[test]
+    fn test_audit_clean() {
+        let data = [block(0), block(0)].concat();
+        assert_eq!(audit(&data), vec![]);
+    }
+
+    #[test]
+    fn test_audit_issues() {
+        let mut bad = block(0);
+        bad[0] = 42;
+        bad[2..4].copy_from_slice(b"AB");
+        bad[9] = 0x07;
+        bad[76..332].fill(b'a');
+        bad[370] = 1;
+        let data = [block(0), vec![0u8; UT_RECORDSIZE], bad, vec![1u8; 10]].concat();
+        let res = audit(&data);
+        assert_eq!(res.iter().map(|i| (i.offset, i.kind.clone())).collect::<Vec<_>>(), vec![
+            (384, IssueKind::ZeroBlock),
+            (768, IssueKind::InvalidType(42)),
+            (768 + 2, IssueKind::NonzeroPadding),
+            (768 + 8, IssueKind::NonPrintable { field: "ut_line" }),
+            (768 + 76, IssueKind::Unterminated { field: "ut_host" }),
+            (768 + 364, IssueKind::NonzeroUnused),
+            (1152, IssueKind::PartialRecord { len: 10 }),
+        ]);
+        assert_eq!(res[6].record, 3);
+        assert_eq!(audit_reader(data.as_slice()).unwrap(), res);
+    }
+}
@@ -0,0 +1,181 @@
+use std::fmt;
+use std::time::{Duration, SystemTime};
+use crate::utmp::Utmp;
+use crate::session::effective_type;
+use crate::{BOOT_TIME, RUN_LVL, SHUTDOWN_TIME};
+
+
+/// How a boot period came to an end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootEnd {
+    /// Closed by a SHUTDOWN_TIME record.
+    Shutdown,
+    /// The next BOOT_TIME record came without a SHUTDOWN_TIME record before it, an unclean shutdown.
+    Crash,
+    /// No later boot or shutdown has been seen yet.
+    StillRunning,
+}
+
+impl fmt::Display for BootEnd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BootEnd::Shutdown => "shutdown",
+            BootEnd::Crash => "crash",
+            BootEnd::StillRunning => "still running",
+        })
+    }
+}
+
+/// A RUN_LVL record within a boot period.
+#[derive(Clone, Debug)]
+pub struct RunlevelChange {
+    pub record: Utmp,
+}
+
+impl RunlevelChange {
+    /// The runlevel before the change, `None` when init did not know it (written as 'N').
+    ///
+    /// init stores the new runlevel in the low byte of `ut_pid` and the previous one in the next byte.
+    pub fn previous(&self) -> Option<char> {
+        runlevel_char((self.record.ut_pid >> 8) as u8)
+    }
+
+    /// The runlevel after the change.
+    pub fn current(&self) -> Option<char> {
+        runlevel_char(self.record.ut_pid as u8)
+    }
+
+    pub fn time(&self) -> Option<SystemTime> {
+        self.record.timestamp()
+    }
+}
+
+fn runlevel_char(b: u8) -> Option<char> {
+    match b {
+        0 | b'N' => None,
+        b if b.is_ascii_graphic() => Some(b as char),
+        _ => None,
+    }
+}
+
+/// The time between a BOOT_TIME record and the shutdown or boot that followed it.
+#[derive(Clone, Debug)]
+pub struct BootPeriod {
+    /// The BOOT_TIME record that opened the period.
+    pub boot: Utmp,
+    /// The record that closed the period, `None` while still running.
+    /// It is the SHUTDOWN_TIME record, or the next BOOT_TIME record after a crash.
+    pub shutdown: Option<Utmp>,
+    /// Why the period ended.
+    pub end: BootEnd,
+    /// Runlevel changes between the boot and the end of the period, in file order.
+    pub runlevels: Vec<RunlevelChange>,
+}
+
+impl BootPeriod {
+    /// Boot time, `None` when the record holds an invalid time.
+    pub fn start(&self) -> Option<SystemTime> {
+        self.boot.timestamp()
+    }
+
+    /// Shutdown time, or the time of the next boot after a crash. `None` while still running
+    /// or when the record holds an invalid time.
+    pub fn end_time(&self) -> Option<SystemTime> {
+        self.shutdown.as_ref().and_then(Utmp::timestamp)
+    }
+
+    /// Time between boot and shutdown.
+    ///
+    /// `None` while still running, when either time is invalid, or when the clock went backwards between the two records.
+    pub fn uptime(&self) -> Option<Duration> {
+        self.end_time()?.duration_since(self.start()?).ok()
+    }
+
+    /// The kernel version the system booted, which init writes into `ut_host`.
+    ///
+    /// Falls back to the first runlevel change that carries one.
+    pub fn kernel(&self) -> String {
+        std::iter::once(&self.boot)
+            .chain(self.runlevels.iter().map(|r| &r.record))
+            .map(Utmp::host)
+            .find(|s| !s.is_empty())
+            .unwrap_or_default()
+    }
+}
+
+/// Rebuild the reboot, shutdown and runlevel history from a record stream in file order, like `last -x`.
+///
+/// Each BOOT_TIME record opens a period. It is closed by the next SHUTDOWN_TIME record,
+/// or, if another BOOT_TIME record comes first, marked as a crash.
+/// RUN_LVL and SHUTDOWN_TIME records before the first boot are ignored.
+///
+/// The periods are returned in boot order.
+pub fn boot_history<I>(records: I) -> Vec<BootPeriod>
+    where I: IntoIterator<Item=Utmp>
+{
+    let mut periods: Vec<BootPeriod> = Vec::new();
+    let mut current: Option<BootPeriod> = None;
+
+    for record in records {
+        match effective_type(&record) {
+            BOOT_TIME => {
+                if let Some(mut period) = current.take() {
+                    period.shutdown = Some(record.clone());
+                    period.end = BootEnd::Crash;
+                    periods.push(period);
+                }
+                current = Some(BootPeriod { boot: record, shutdown: None, end: BootEnd::StillRunning, runlevels: Vec::new() });
+            }
+            SHUTDOWN_TIME => {
+                if let Some(mut period) = current.take() {
+                    period.shutdown = Some(record);
+                    period.end = BootEnd::Shutdown;
+                    periods.push(period);
+                }
+            }
+            RUN_LVL => {
+                if let Some(period) = current.as_mut() {
+                    period.runlevels.push(RunlevelChange { record });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    periods.extend(current);
+    periods
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::USER_PROCESS;
+    use crate::fixture::Record;
+
+    fn record(ut_type: i32, pid: i32, user: &str, host: &str, sec: i64) -> Utmp {
+        Record::new(ut_type, sec).pid(pid).line("~").id("~~").user(user).host(host).build()
+    }
+
+    #[test]
+    fn test_boot_history() {
+        let records = vec![
+            record(BOOT_TIME, 0, "reboot", "5.4.17", 100),
+            record(RUN_LVL, 0x4e35, "runlevel", "5.4.17", 110),
+            record(USER_PROCESS, 42, "alice", "", 120),
+            record(RUN_LVL, 0x3530, "runlevel", "5.4.17", 190),
+            record(RUN_LVL, 0, "shutdown", "5.4.17", 200),
+            record(BOOT_TIME, 0, "reboot", "", 300),
+            record(RUN_LVL, 0x4e33, "runlevel", "5.15.0", 310),
+            record(BOOT_TIME, 0, "reboot", "5.15.0", 500),
+        ];
+        let res = boot_history(records);
+        assert_eq!(res.iter().map(|p| (p.kernel(), p.end, p.uptime().map(|d| d.as_secs()))).collect::<Vec<_>>(), vec![
+            ("5.4.17".to_string(), BootEnd::Shutdown, Some(100)),
+            ("5.15.0".to_string(), BootEnd::Crash, Some(200)),
+            ("5.15.0".to_string(), BootEnd::StillRunning, None),
+        ]);
+        assert_eq!(res[0].runlevels.iter().map(|r| (r.previous(), r.current())).collect::<Vec<_>>(),
+                   vec![(None, Some('5')), (Some('5'), Some('0'))]);
+    }
+}
@@ -0,0 +1,237 @@
+use std::io::Read;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::reader::Window;
+use crate::recover::is_text;
+use crate::utmp::Utmp;
+use crate::{Error, Format, Layout, LOGIN_PROCESS, RUN_LVL, SHUTDOWN_TIME, USER_PROCESS, UT_RECORDSIZE};
+
+
+/// A record found by [`Carver`], where it was and how sure the carver is about it.
+#[derive(Clone, Debug)]
+pub struct Carved {
+    /// Byte offset of the record in the input.
+    pub offset: u64,
+    pub record: Utmp,
+    /// The layout the record was read in.
+    pub layout: Layout,
+    /// From 0 to 1, see [`score`].
+    pub confidence: f64,
+}
+
+/// Thresholds of [`Carver`].
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Candidates scoring below this are dropped.
+    pub min_confidence: f64,
+    /// Timestamps from `since` up to `until` count as plausible.
+    pub since: SystemTime,
+    pub until: SystemTime,
+    /// Layouts tried at every offset, the first of the best scoring ones wins.
+    pub layouts: Vec<Layout>,
+}
+
+impl Default for Options {
+    /// A confidence of 0.75, times from 1990 up to a day from now, and every known layout but BSD.
+    ///
+    /// BSD records have no `ut_type`, so plain text next to a timestamp would pass for one.
+    fn default() -> Self {
+        Options {
+            min_confidence: 0.75,
+            since: UNIX_EPOCH + Duration::from_secs(631_152_000),
+            until: SystemTime::now() + Duration::from_secs(24 * 3600),
+            layouts: Layout::all().into_iter().filter(|l| l.format != Format::Bsd).collect(),
+        }
+    }
+}
+
+/// How much a record read at some offset looks like a real one, from 0 to 1.
+///
+/// A `ut_type` other than EMPTY and within `RUN_LVL..=SHUTDOWN_TIME` and a `ut_tv.tv_sec` within
+/// the options' range are required, anything else scores 0. They count 0.25, the rest is the sum of:
+///
+/// | check                                    | weight |
+/// |------------------------------------------|--------|
+/// | `ut_line` printable and NUL-terminated   | 0.25   |
+/// | `ut_user` printable and NUL-terminated   | 0.20   |
+/// | `ut_host` printable and NUL-terminated   | 0.10   |
+/// | `ut_id` printable                        | 0.05   |
+/// | `ut_tv.tv_usec` below one second         | 0.05   |
+/// | `__unused` all zero                      | 0.10   |
+///
+/// An empty `ut_line` or `ut_user` scores nothing on LOGIN_PROCESS and USER_PROCESS records, which always have them.
+pub fn score(utmp: &Utmp, options: &Options) -> f64 {
+    if !(RUN_LVL..=SHUTDOWN_TIME).contains(&utmp.ut_type) {
+        return 0.0;
+    }
+    // 全零或稀疏的数据中只有类型像记录，没有合理的时间
+    if !utmp.timestamp().is_some_and(|time| options.since <= time && time < options.until) {
+        return 0.0;
+    }
+    let login = matches!(utmp.ut_type, LOGIN_PROCESS | USER_PROCESS);
+    let named = |field: &[u8]| is_text(field, true) && !(login && field[0] == 0);
+    [
+        (true, 25),
+        (named(&utmp.ut_line), 25),
+        (named(&utmp.ut_user), 20),
+        (is_text(&utmp.ut_host, true), 10),
+        (is_text(&utmp.ut_id, false), 5),
+        ((0..1_000_000).contains(&utmp.ut_time_usec), 5),
+        (utmp.__unused == [0u8; 20], 10),
+    ].iter()
+        .filter(|(passed, _)| *passed)
+        .map(|(_, weight)| weight)
+        .sum::<u32>() as f64 / 100.0
+}
+
+/// Finds records in arbitrary binary input, such as a raw disk image, unallocated space or a memory dump.
+///
+/// Every byte offset is a candidate, not only multiples of the record size. A candidate
+/// scoring at least `min_confidence` is taken as a record and the scan goes on after it,
+/// so carved records never overlap. Only a window of the input is held in memory.
+///
+/// Carved records are yielded in offset order.
+pub struct Carver<R> {
+    window: Window<R>,
+    options: Options,
+}
+
+impl<R: Read> Carver<R> {
+    pub fn new(inner: R) -> Self {
+        Self::with_options(inner, Options::default())
+    }
+
+    pub fn with_options(inner: R, options: Options) -> Self {
+        // 读错误的记录序号按最大的布局计算
+        let record_size = options.layouts.iter().map(Layout::record_size).max().unwrap_or(UT_RECORDSIZE);
+        Carver { window: Window::new(inner, 1 << 20, record_size), options }
+    }
+}
+
+impl<R: Read> Carver<R> {
+    /// The best scoring layout for the bytes at `at`, with its record and score.
+    fn best_at(&self, bytes: &[u8]) -> Option<(Layout, Utmp, f64)> {
+        let mut best: Option<(Layout, Utmp, f64)> = None;
+        for layout in &self.options.layouts {
+            // 先只看ut_type，绝大多数偏移在这里就被排除。
+            if !layout.peek_type(bytes).is_some_and(|t| (RUN_LVL..=SHUTDOWN_TIME).contains(&t)) {
+                continue;
+            }
+            if let Ok((_, utmp)) = layout.parse(bytes) {
+                let confidence = score(&utmp, &self.options);
+                if best.as_ref().is_none_or(|b| confidence > b.2) {
+                    best = Some((*layout, utmp, confidence));
+                }
+            }
+        }
+        best
+    }
+}
+
+impl<R: Read> Iterator for Carver<R> {
+    type Item = Result<Carved, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let size = self.options.layouts.iter().map(Layout::record_size).max()?;
+        let min_size = self.options.layouts.iter().map(Layout::record_size).min()?;
+        loop {
+            if let Err(e) = self.window.fill(size) {
+                return Some(Err(e));
+            }
+            let avail = self.window.bytes();
+            if avail.len() < min_size {
+                return None;
+            }
+
+            let found = (0..=avail.len() - min_size)
+                .find_map(|at| self.best_at(&avail[at..])
+                    .filter(|best| best.2 >= self.options.min_confidence)
+                    .map(|best| (at, best)));
+            let (at, (layout, record, confidence)) = match found {
+                Some(found) => found,
+                None => {
+                    // 末尾不足一条记录的字节留到下一轮，与新读入的数据一起检查。
+                    let keep = if avail.len() >= size { size - 1 } else { 0 };
+                    self.window.consume(avail.len() - keep);
+                    if keep == 0 {
+                        return None;
+                    }
+                    continue;
+                }
+            };
+            let offset = self.window.offset() + at as u64;
+            self.window.consume(at + layout.record_size());
+            return Some(Ok(Carved { offset, record, layout, confidence }));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::block;
+    use crate::UT_RECORDSIZE;
+
+    fn parse(block: &[u8]) -> Utmp {
+        Layout::native().parse(block).unwrap().1
+    }
+
+    #[test]
+    fn test_score() {
+        let options = Options::default();
+        assert_eq!(score(&parse(&block(1)), &options), 1.0);
+
+        let mut b = block(1);
+        b[370] = 1;
+        assert_eq!(score(&parse(&b), &options), 0.9);
+        b[340..344].fill(0);
+        assert_eq!(score(&parse(&b), &options), 0.0);
+
+        // 登录记录的空用户名不得分
+        let mut b = block(1);
+        b[44..49].fill(0);
+        assert_eq!(score(&parse(&b), &options), 0.8);
+        b[8..13].fill(0);
+        assert_eq!(score(&parse(&b), &options), 0.55);
+
+        assert_eq!(score(&parse(&[0u8; UT_RECORDSIZE]), &options), 0.0);
+    }
+
+    #[test]
+    fn test_carver_no_false_records() {
+        let carve = |data: &[u8]| Carver::new(data).collect::<Result<Vec<_>, _>>().unwrap();
+
+        let mut zeros = vec![0u8; 4096];
+        zeros[1000] = 7;
+        assert!(carve(&zeros).is_empty());
+
+        // 每64字节一个小整数
+        let mut sparse = vec![0u8; 4096];
+        for (n, at) in (0..sparse.len()).step_by(64).enumerate() {
+            sparse[at] = (n % 11 + 1) as u8;
+        }
+        assert!(carve(&sparse).is_empty());
+    }
+
+    #[test]
+    fn test_carver_any_alignment() {
+        let mut noise = (0..1000u32).map(|i| (i * 7919 % 251) as u8).collect::<Vec<_>>();
+        noise[500..504].copy_from_slice(&7i32.to_ne_bytes());
+        let data = [vec![0xffu8; 13], block(1), noise, block(2), block(3), vec![0u8; 100]].concat();
+
+        let res = Carver::new(data.as_slice()).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(res.iter().map(|c| (c.offset, c.record.ut_pid)).collect::<Vec<_>>(),
+                   vec![(13, 1), (1397, 2), (1781, 3)]);
+        assert!(res.iter().all(|c| c.confidence == 1.0));
+    }
+
+    #[test]
+    fn test_carver_other_byte_order() {
+        let native = Layout::native();
+        let other = Layout::all().into_iter().find(|l| *l != native).unwrap();
+        let data = [vec![0u8; 50], other.to_bytes(&parse(&block(9)))].concat();
+
+        let res = Carver::new(data.as_slice()).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(res.iter().map(|c| (c.offset, c.layout, c.record.ut_pid)).collect::<Vec<_>>(), vec![(50, other, 9)]);
+    }
+}
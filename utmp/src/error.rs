@@ -0,0 +1,68 @@
+use std::io;
+use thiserror::Error;
+
+
+/// Why a record could not be read, and where.
+///
+/// Every variant carries the index of the record, counted from 0 in file order,
+/// and the byte offset in the input: the offset of the failing field where there is one,
+/// otherwise the start of the record.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The input ends inside a record, `len` bytes of it are present.
+    ///
+    /// A utmp file is a whole number of records, bytes after the last complete one are never
+    /// parsed as a record. They are reported with this error after the complete records.
+    #[error("record {record} at offset {offset}: input ends inside {field}, only {len} bytes of the record present")]
+    Truncated { record: usize, offset: u64, field: &'static str, len: usize },
+
+    /// A field holds a value the record format does not allow.
+    #[error("record {record} at offset {offset}: {field} is invalid, {reason}")]
+    InvalidField { record: usize, offset: u64, field: &'static str, reason: String },
+
+    /// Reading the source failed.
+    #[error("record {record} at offset {offset}: {source}")]
+    Io { record: usize, offset: u64, source: io::Error },
+}
+
+impl Error {
+    /// Index of the record the error belongs to.
+    pub fn record(&self) -> usize {
+        match self {
+            Error::Truncated { record, .. } | Error::InvalidField { record, .. } | Error::Io { record, .. } => *record,
+        }
+    }
+
+    /// Byte offset of the failing field, or of the record.
+    pub fn offset(&self) -> u64 {
+        match self {
+            Error::Truncated { offset, .. } | Error::InvalidField { offset, .. } | Error::Io { offset, .. } => *offset,
+        }
+    }
+
+    /// Name of the failing field, `None` for read errors.
+    pub fn field(&self) -> Option<&'static str> {
+        match self {
+            Error::Truncated { field, .. } | Error::InvalidField { field, .. } => Some(field),
+            Error::Io { .. } => None,
+        }
+    }
+
+    /// Whether records after this one can still be read.
+    ///
+    /// An invalid field spoils only its own record, the input is still aligned on the next one.
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, Error::InvalidField { .. })
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io { source, .. } => source,
+            Error::Truncated { .. } => io::Error::new(io::ErrorKind::UnexpectedEof, e),
+            Error::InvalidField { .. } => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::time::SystemTime;
+use crate::utmp::Utmp;
+use crate::{Error, Layout};
+
+
+/// Random access to the records of a file by positioned reads, nothing is loaded up front.
+///
+/// Records are a fixed size, so record `i` lives at byte `i * record_size` and
+/// reading the last few records of a 2 GB wtmp costs the same as of a 2 KB one.
+/// Bytes after the last complete record are not counted as a record.
+pub struct UtmpFile {
+    file: File,
+    layout: Layout,
+    len: usize,
+}
+
+impl UtmpFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_file(File::open(path)?)
+    }
+
+    /// Reads records in the native layout.
+    pub fn from_file(file: File) -> io::Result<Self> {
+        Self::with_layout(file, Layout::native())
+    }
+
+    /// Reads records written in `layout`, e.g. as found by [`Layout::detect_reader`].
+    pub fn with_layout(file: File, layout: Layout) -> io::Result<Self> {
+        let len = (file.metadata()?.len() / layout.record_size() as u64) as usize;
+        Ok(UtmpFile { file, layout, len })
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Number of complete records, as of opening.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The record at `index`, `None` past the end.
+    pub fn get(&self, index: usize) -> Result<Option<Utmp>, Error> {
+        if index >= self.len {
+            return Ok(None);
+        }
+        self.layout.parse_record(&self.block(index)?, index).map(Some)
+    }
+
+    /// The bytes of the record with `index`, which must be in the file.
+    fn block(&self, index: usize) -> Result<Vec<u8>, Error> {
+        let mut buf = vec![0u8; self.layout.record_size()];
+        let offset = (index * buf.len()) as u64;
+        read_exact_at(&self.file, &mut buf, offset).map_err(|source| Error::Io { record: index, offset, source })?;
+        Ok(buf)
+    }
+
+    /// All records in file order, `.rev()` walks them from the end.
+    pub fn iter(&self) -> Iter<'_> {
+        self.iter_range(0..self.len)
+    }
+
+    /// The records in `range`, clamped to the file.
+    pub fn iter_range(&self, range: Range<usize>) -> Iter<'_> {
+        let end = range.end.min(self.len);
+        Iter { file: self, front: range.start.min(end), back: end }
+    }
+
+    /// Index of the first record not older than `time`, by binary search.
+    ///
+    /// wtmp is appended in time order, so this is exact for files without clock changes.
+    /// Where NEW_TIME/OLD_TIME records or EMPTY slots break the order, the result lands on one of the candidates.
+    pub fn partition_point_by_time(&self, time: SystemTime) -> Result<usize, Error> {
+        let (mut lo, mut hi) = (0, self.len);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            // 无效的时间当作比任何时间都早
+            if self.timestamp(mid)? < Some(time) {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        Ok(lo)
+    }
+
+    /// The time of the record with `index`, read without checking its other fields,
+    /// so an invalid `ut_type` on the way does not stop a bisection.
+    fn timestamp(&self, index: usize) -> Result<Option<SystemTime>, Error> {
+        Ok(self.layout.parse(&self.block(index)?).ok().and_then(|(_, utmp)| utmp.timestamp()))
+    }
+
+    /// The records with `from <= time < to`, found by binary search, see [`UtmpFile::partition_point_by_time`].
+    pub fn range_by_time(&self, from: SystemTime, to: SystemTime) -> Result<Iter<'_>, Error> {
+        let start = self.partition_point_by_time(from)?;
+        let end = self.partition_point_by_time(to)?;
+        Ok(self.iter_range(start..end.max(start)))
+    }
+}
+
+/// Iterator over the records of a [`UtmpFile`], reading one record per step.
+pub struct Iter<'a> {
+    file: &'a UtmpFile,
+    front: usize,
+    back: usize,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Result<Utmp, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front >= self.back {
+            return None;
+        }
+        self.front += 1;
+        self.file.get(self.front - 1).transpose()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.back - self.front, Some(self.back - self.front))
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front >= self.back {
+            return None;
+        }
+        self.back -= 1;
+        self.file.get(self.back).transpose()
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::time::{Duration, UNIX_EPOCH};
+    use crate::UT_RECORDSIZE;
+
+    fn temp_file(times: &[u32], trailing: usize) -> File {
+        temp_file_with_invalid(times, trailing, None)
+    }
+
+    /// Like `temp_file`, with an invalid `ut_type` in record `invalid`.
+    fn temp_file_with_invalid(times: &[u32], trailing: usize, invalid: Option<usize>) -> File {
+        let path = std::env::temp_dir().join(format!("utmp-file-test-{}-{}-{:?}", std::process::id(), times.len(), invalid));
+        let mut file = File::create(&path).unwrap();
+        for (i, sec) in times.iter().enumerate() {
+            let mut block = [0u8; UT_RECORDSIZE];
+            block[0] = if invalid == Some(i) { 99 } else { crate::USER_PROCESS as u8 };
+            block[4] = i as u8;
+            block[340..344].copy_from_slice(&sec.to_ne_bytes());
+            file.write_all(&block).unwrap();
+        }
+        file.write_all(&vec![0u8; trailing]).unwrap();
+        let file = File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        file
+    }
+
+    fn at(sec: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(sec)
+    }
+
+    #[test]
+    fn test_utmp_file_random_access() {
+        let file = UtmpFile::from_file(temp_file(&[10, 20, 30, 40, 50], 7)).unwrap();
+        assert_eq!(file.len(), 5);
+        assert_eq!(file.get(3).unwrap().map(|u| u.ut_pid), Some(3));
+        assert!(file.get(5).unwrap().is_none());
+
+        let tail = file.iter().rev().take(2).map(|r| r.unwrap().ut_pid).collect::<Vec<_>>();
+        assert_eq!(tail, vec![4, 3]);
+    }
+
+    #[test]
+    fn test_utmp_file_time_bisection() {
+        let file = UtmpFile::from_file(temp_file(&[10, 20, 20, 30, 40, 50], 0)).unwrap();
+        assert_eq!(file.partition_point_by_time(at(0)).unwrap(), 0);
+        assert_eq!(file.partition_point_by_time(at(20)).unwrap(), 1);
+        assert_eq!(file.partition_point_by_time(at(25)).unwrap(), 3);
+        assert_eq!(file.partition_point_by_time(at(99)).unwrap(), 6);
+
+        let pids = file.range_by_time(at(20), at(40)).unwrap().map(|r| r.unwrap().ut_pid).collect::<Vec<_>>();
+        assert_eq!(pids, vec![1, 2, 3]);
+        assert_eq!(file.range_by_time(at(40), at(20)).unwrap().count(), 0);
+
+        // 二分路径上的无效记录只取时间，不使查找失败
+        let file = UtmpFile::from_file(temp_file_with_invalid(&[10, 20, 30, 40, 50, 60, 70], 0, Some(3))).unwrap();
+        assert!(file.get(3).is_err());
+        assert_eq!(file.partition_point_by_time(at(35)).unwrap(), 3);
+        assert_eq!(file.partition_point_by_time(at(45)).unwrap(), 4);
+    }
+}
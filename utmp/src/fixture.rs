@@ -0,0 +1,68 @@
+use crate::utmp::Utmp;
+use crate::{UT_HOSTSIZE, UT_LINESIZE, UT_NAMESIZE, UT_RECORDSIZE};
+
+
+/// Builds the records the tests feed to the analyses: a type and a time, every other field empty until set.
+pub(crate) struct Record(Utmp);
+
+impl Record {
+    pub(crate) fn new(ut_type: i32, sec: i64) -> Self {
+        Record(Utmp {
+            ut_type,
+            __ut_pad1: [0u8; 2],
+            ut_pid: 0,
+            ut_line: [0u8; UT_LINESIZE],
+            ut_id: [0u8; 4],
+            ut_user: [0u8; UT_NAMESIZE],
+            ut_host: [0u8; UT_HOSTSIZE],
+            ut_termination: 0,
+            ut_exit: 0,
+            ut_session: 0,
+            ut_time_sec: sec,
+            ut_time_usec: 0,
+            ut_addr_v6: [0; 4],
+            __unused: [0u8; 20],
+        })
+    }
+
+    pub(crate) fn pid(mut self, pid: i32) -> Self {
+        self.0.ut_pid = pid;
+        self
+    }
+
+    pub(crate) fn line(mut self, line: &str) -> Self {
+        self.0.ut_line[..line.len()].copy_from_slice(line.as_bytes());
+        self
+    }
+
+    pub(crate) fn id(mut self, id: &str) -> Self {
+        self.0.ut_id[..id.len()].copy_from_slice(id.as_bytes());
+        self
+    }
+
+    pub(crate) fn user(mut self, user: &str) -> Self {
+        self.0.ut_user[..user.len()].copy_from_slice(user.as_bytes());
+        self
+    }
+
+    pub(crate) fn host(mut self, host: &str) -> Self {
+        self.0.ut_host[..host.len()].copy_from_slice(host.as_bytes());
+        self
+    }
+
+    pub(crate) fn build(self) -> Utmp {
+        self.0
+    }
+}
+
+/// A native glibc USER_PROCESS record of alice on pts/0 in 2020, laid out by hand.
+pub(crate) fn block(pid: u8) -> Vec<u8> {
+    let mut b = vec![0u8; UT_RECORDSIZE];
+    b[0] = crate::USER_PROCESS as u8;
+    b[4] = pid;
+    b[8..13].copy_from_slice(b"pts/0");
+    b[40..44].copy_from_slice(b"ts/0");
+    b[44..49].copy_from_slice(b"alice");
+    b[340..344].copy_from_slice(&1_600_000_000u32.to_ne_bytes());
+    b
+}
@@ -0,0 +1,933 @@
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::str::FromStr;
+use nom::IResult;
+use nom::bytes::complete::take;
+use nom::combinator::{map, map_res};
+use nom::sequence::pair;
+use nom::multi::fill;
+use nom::number::Endianness;
+use nom::number::complete::{i16, i32, i64, u32};
+use thiserror::Error;
+use crate::reader::read_block;
+use crate::utmp::Utmp;
+use crate::{Error, BOOT_TIME, DEAD_PROCESS, EMPTY, NEW_TIME, OLD_TIME, SHUTDOWN_TIME, USER_PROCESS, UT_HOSTSIZE, UT_LINESIZE, UT_NAMESIZE, UT_RECORDSIZE};
+
+
+/// Byte order of the integers in a record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    /// The byte order of the machine running this code.
+    pub fn native() -> Self {
+        if cfg!(target_endian = "big") { Endian::Big } else { Endian::Little }
+    }
+
+    fn nom(self) -> Endianness {
+        match self {
+            Endian::Little => Endianness::Little,
+            Endian::Big => Endianness::Big,
+        }
+    }
+}
+
+/// The C struct a file was written with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Format {
+    /// glibc `struct utmp` on Linux: 384 bytes with a 32-bit `ut_tv`, also on 64-bit hosts that keep
+    /// it for 32-bit compatibility, such as x86_64, ppc64 and s390x.
+    ///
+    /// `tv_sec` is read as unsigned, as glibc 2.40 does, so times after 2038 decode up to 2106.
+    /// `ut_type` is a 16-bit short followed by 2 bytes of padding, kept as [`Utmp::__ut_pad1`].
+    Glibc,
+    /// musl `struct utmpx` on 64-bit Linux, e.g. Alpine on x86_64 or aarch64: 400 bytes,
+    /// a 16-bit `ut_type` and a 64-bit `ut_tv`. glibc writes the same bytes on 64-bit hosts
+    /// without the 32-bit compatibility, such as aarch64, riscv64 and loongarch64, and the name
+    /// `glibc64` selects this format.
+    ///
+    /// The padding after `ut_type` is kept as [`Utmp::__ut_pad1`], the rest is not and
+    /// [`Layout::to_bytes`] writes it as zero.
+    Musl,
+    /// Linux libc5 `struct utmp`: 56 bytes with a 16-bit `ut_type`, a signed 32-bit `ut_time` and no microseconds.
+    ///
+    /// `ut_line`, `ut_id`, `ut_user` and `ut_host` are 12, 2, 8 and 16 bytes and fill the start
+    /// of the wider fields of [`Utmp`], the fields libc5 lacks read as zero. [`Layout::to_bytes`]
+    /// cuts longer values to the libc5 sizes, so only records read as libc5 are written back unchanged.
+    Libc5,
+    /// The utmp of the BSDs before utmpx and of Mac OS X before 10.5: 36 bytes holding only
+    /// `ut_line`, `ut_name`, `ut_host` and a signed 32-bit `ut_time`.
+    ///
+    /// There is no `ut_type`, it is derived the way `last(1)` reads these files: terminal "~"
+    /// with the name "reboot" or "shutdown" is BOOT_TIME or SHUTDOWN_TIME, "|" and "{" are
+    /// OLD_TIME and NEW_TIME, a name is a USER_PROCESS, a terminal without one a DEAD_PROCESS.
+    /// Only those four fields are written back by [`Layout::to_bytes`].
+    Bsd,
+    /// Mac OS X 10.5 and later `utmpx`: 628-byte records, the first of them a SIGNATURE
+    /// header with the name "utmpx-1.00". OLD_TIME and NEW_TIME have each other's values
+    /// there and are swapped while reading and writing.
+    ///
+    /// `ut_user` is 256 bytes and cut to the 32 of [`Utmp::ut_user`], the first 20 of the
+    /// 64 reserved bytes are kept as [`Utmp::__unused`].
+    Macos,
+}
+
+/// How records are laid out in a file: the struct and the byte order of its integers.
+///
+/// Files are read as written by the host they come from, so a wtmp copied from s390x or ppc64
+/// decodes the same on x86. Strings and `ut_addr_v6` are byte arrays and read the same in any order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Layout {
+    pub format: Format,
+    pub endian: Endian,
+}
+
+/// Fields of the glibc record in file order: name, offset, size.
+const GLIBC_FIELDS: [(&str, usize, usize); 14] = [
+    ("ut_type", 0, 2),
+    ("__ut_pad1", 2, 2),
+    ("ut_pid", 4, 4),
+    ("ut_line", 8, 32),
+    ("ut_id", 40, 4),
+    ("ut_user", 44, 32),
+    ("ut_host", 76, 256),
+    ("ut_termination", 332, 2),
+    ("ut_exit", 334, 2),
+    ("ut_session", 336, 4),
+    ("ut_time_sec", 340, 4),
+    ("ut_time_usec", 344, 4),
+    ("ut_addr_v6", 348, 16),
+    ("__unused", 364, 20),
+];
+
+/// Fields of the 64-bit musl record, little-endian: `ut_session` and its padding swap places on big-endian.
+const MUSL_FIELDS_LE: [(&str, usize, usize); 16] = [
+    ("ut_type", 0, 2),
+    ("__ut_pad1", 2, 2),
+    ("ut_pid", 4, 4),
+    ("ut_line", 8, 32),
+    ("ut_id", 40, 4),
+    ("ut_user", 44, 32),
+    ("ut_host", 76, 256),
+    ("ut_termination", 332, 2),
+    ("ut_exit", 334, 2),
+    ("ut_session", 336, 4),
+    ("__ut_pad2", 340, 4),
+    ("ut_time_sec", 344, 8),
+    ("ut_time_usec", 352, 8),
+    ("ut_addr_v6", 360, 16),
+    ("__unused", 376, 20),
+    ("__pad", 396, 4),
+];
+const MUSL_FIELDS_BE: [(&str, usize, usize); 16] = [
+    ("ut_type", 0, 2),
+    ("__ut_pad1", 2, 2),
+    ("ut_pid", 4, 4),
+    ("ut_line", 8, 32),
+    ("ut_id", 40, 4),
+    ("ut_user", 44, 32),
+    ("ut_host", 76, 256),
+    ("ut_termination", 332, 2),
+    ("ut_exit", 334, 2),
+    ("__ut_pad2", 336, 4),
+    ("ut_session", 340, 4),
+    ("ut_time_sec", 344, 8),
+    ("ut_time_usec", 352, 8),
+    ("ut_addr_v6", 360, 16),
+    ("__unused", 376, 20),
+    ("__pad", 396, 4),
+];
+const MUSL_RECORDSIZE: usize = 400;
+
+/// Fields of the libc5 record, `ut_addr` is a `long` holding an IPv4 address.
+const LIBC5_FIELDS: [(&str, usize, usize); 10] = [
+    ("ut_type", 0, 2),
+    ("__ut_pad1", 2, 2),
+    ("ut_pid", 4, 4),
+    ("ut_line", 8, 12),
+    ("ut_id", 20, 2),
+    ("__ut_pad2", 22, 2),
+    ("ut_time_sec", 24, 4),
+    ("ut_user", 28, 8),
+    ("ut_host", 36, 16),
+    ("ut_addr_v6", 52, 4),
+];
+const LIBC5_RECORDSIZE: usize = 56;
+
+/// Fields of the BSD record, `ut_user` being `ut_name` there.
+const BSD_FIELDS: [(&str, usize, usize); 4] = [
+    ("ut_line", 0, 8),
+    ("ut_user", 8, 8),
+    ("ut_host", 16, 16),
+    ("ut_time_sec", 32, 4),
+];
+const BSD_RECORDSIZE: usize = 36;
+
+/// Fields of the Mac OS X utmpx record, `__unused` being `ut_pad`.
+const MACOS_FIELDS: [(&str, usize, usize); 10] = [
+    ("ut_user", 0, 256),
+    ("ut_id", 256, 4),
+    ("ut_line", 260, 32),
+    ("ut_pid", 292, 4),
+    ("ut_type", 296, 2),
+    ("__ut_pad1", 298, 2),
+    ("ut_time_sec", 300, 4),
+    ("ut_time_usec", 304, 4),
+    ("ut_host", 308, 256),
+    ("__unused", 564, 64),
+];
+const MACOS_RECORDSIZE: usize = 628;
+
+/// Records looked at by [`Layout::detect`].
+const DETECT_RECORDS: usize = 64;
+/// `PID_MAX_LIMIT` on 64-bit Linux.
+const PID_MAX: i32 = 4 * 1024 * 1024;
+
+impl Layout {
+    pub const fn new(format: Format, endian: Endian) -> Self {
+        Layout { format, endian }
+    }
+
+    /// glibc in the byte order of this machine, what [`crate::take_one_record`] reads.
+    pub fn native() -> Self {
+        Layout::new(Format::Glibc, Endian::native())
+    }
+
+    /// Every layout [`Layout::detect`] chooses from, the native one first.
+    pub fn all() -> Vec<Layout> {
+        let native = Endian::native();
+        let other = if native == Endian::Little { Endian::Big } else { Endian::Little };
+        [Format::Glibc, Format::Musl, Format::Libc5, Format::Bsd, Format::Macos].iter()
+            .flat_map(|format| [Layout::new(*format, native), Layout::new(*format, other)])
+            .collect()
+    }
+
+    /// Size of one record in bytes.
+    pub fn record_size(&self) -> usize {
+        match self.format {
+            Format::Glibc => UT_RECORDSIZE,
+            Format::Musl => MUSL_RECORDSIZE,
+            Format::Libc5 => LIBC5_RECORDSIZE,
+            Format::Bsd => BSD_RECORDSIZE,
+            Format::Macos => MACOS_RECORDSIZE,
+        }
+    }
+
+    /// Fields of a record in file order: name, offset, size.
+    pub fn fields(&self) -> &'static [(&'static str, usize, usize)] {
+        match (self.format, self.endian) {
+            (Format::Glibc, _) => &GLIBC_FIELDS,
+            (Format::Musl, Endian::Little) => &MUSL_FIELDS_LE,
+            (Format::Musl, Endian::Big) => &MUSL_FIELDS_BE,
+            (Format::Libc5, _) => &LIBC5_FIELDS,
+            (Format::Bsd, _) => &BSD_FIELDS,
+            (Format::Macos, _) => &MACOS_FIELDS,
+        }
+    }
+
+    /// Offset and size of the field called `name`.
+    pub(crate) fn field(&self, name: &str) -> Option<(usize, usize)> {
+        self.fields().iter().find(|(n, _, _)| *n == name).map(|(_, offset, size)| (*offset, *size))
+    }
+
+    /// The field that byte `offset` of a record belongs to, and where it starts.
+    pub(crate) fn field_at(&self, offset: usize) -> (&'static str, usize) {
+        let fields = self.fields();
+        fields.iter()
+            .rev()
+            .find(|(_, start, _)| *start <= offset)
+            .map(|(name, start, _)| (*name, *start))
+            .unwrap_or((fields[0].0, 0))
+    }
+
+    /// `ut_type` of the record starting at `bytes`, without parsing the rest.
+    pub(crate) fn peek_type(&self, bytes: &[u8]) -> Option<i32> {
+        let e = self.endian.nom();
+        let res: IResult<&[u8], i32> = match self.format {
+            Format::Glibc | Format::Musl | Format::Libc5 => map(i16(e), i32::from)(bytes),
+            // BSD记录没有类型字段，只能由整条记录推出。
+            Format::Bsd => map(|i| parse_bsd(e, i), |utmp| utmp.ut_type)(bytes),
+            Format::Macos => map(i16(e), macos_type)(bytes.get(296..)?),
+        };
+        res.ok().map(|(_, ut_type)| ut_type)
+    }
+
+    /// nom parser for one record. Never panics, and checks no value.
+    pub fn parse<'a>(&self, i: &'a [u8]) -> IResult<&'a [u8], Utmp> {
+        match self.format {
+            Format::Glibc => parse_glibc(self.endian.nom(), i),
+            Format::Musl => parse_musl(self.endian, i),
+            Format::Libc5 => parse_libc5(self.endian.nom(), i),
+            Format::Bsd => parse_bsd(self.endian.nom(), i),
+            Format::Macos => parse_macos(self.endian.nom(), i),
+        }
+    }
+
+    /// Parse the record with index `record` from `block`, which holds its bytes from the start.
+    ///
+    /// Unlike [`Layout::parse`] it checks `ut_type`, and tells which record and field failed.
+    /// Bytes after the first record are ignored.
+    pub fn parse_record(&self, block: &[u8], record: usize) -> Result<Utmp, Error> {
+        let base = (record * self.record_size()) as u64;
+        if block.len() < self.record_size() {
+            let (field, start) = self.field_at(block.len());
+            return Err(Error::Truncated { record, offset: base + start as u64, field, len: block.len() });
+        }
+        let utmp = match self.parse(block) {
+            Ok((_, utmp)) => utmp,
+            // 长度足够时解析失败只能是取值超出范围，由出错的位置得到字段。
+            Err(nom::Err::Error(e) | nom::Err::Failure(e)) => {
+                let (field, start) = self.field_at(block.len() - e.input.len());
+                return Err(Error::InvalidField { record, offset: base + start as u64, field, reason: "value out of range".to_string() });
+            }
+            Err(nom::Err::Incomplete(_)) => {
+                return Err(Error::Truncated { record, offset: base, field: self.fields()[0].0, len: block.len() });
+            }
+        };
+        if let Err(e) = utmp.record_type() {
+            let offset = base + self.field("ut_type").map_or(0, |(offset, _)| offset as u64);
+            return Err(Error::InvalidField { record, offset, field: "ut_type", reason: format!("{} is not a known record type", e.0) });
+        }
+        Ok(utmp)
+    }
+
+    /// Parse a whole file held in memory, see [`crate::parse_records`].
+    pub fn parse_records(&self, data: &[u8]) -> Result<Vec<Utmp>, Error> {
+        let mut records = Vec::with_capacity(data.len() / self.record_size());
+        for (index, block) in data.chunks(self.record_size()).enumerate() {
+            records.push(self.parse_record(block, index)?);
+        }
+        Ok(records)
+    }
+
+    /// The record as laid out in a file of this layout, byte for byte what [`Layout::parse`] read.
+    /// Padding the layout does not keep is written as zeros.
+    pub fn to_bytes(&self, utmp: &Utmp) -> Vec<u8> {
+        let e = self.endian;
+        let mut out = Vec::with_capacity(self.record_size());
+        match self.format {
+            Format::Glibc => {
+                out.extend_from_slice(&to_bytes16(utmp.ut_type as u16, e));
+                out.extend_from_slice(&utmp.__ut_pad1);
+                out.extend_from_slice(&to_bytes32(utmp.ut_pid as u32, e));
+                out.extend_from_slice(&utmp.ut_line);
+                out.extend_from_slice(&utmp.ut_id);
+                out.extend_from_slice(&utmp.ut_user);
+                out.extend_from_slice(&utmp.ut_host);
+                out.extend_from_slice(&to_bytes16(utmp.ut_termination as u16, e));
+                out.extend_from_slice(&to_bytes16(utmp.ut_exit as u16, e));
+                out.extend_from_slice(&to_bytes32(utmp.ut_session as u32, e));
+                // 32位的字段放不下的时间被截断，读入时已是32位的记录原样写回。
+                out.extend_from_slice(&to_bytes32(utmp.ut_time_sec as u32, e));
+                out.extend_from_slice(&to_bytes32(utmp.ut_time_usec as u32, e));
+                for word in utmp.ut_addr_v6 {
+                    out.extend_from_slice(&word.to_ne_bytes());
+                }
+                out.extend_from_slice(&utmp.__unused);
+            }
+            Format::Musl => {
+                out.extend_from_slice(&to_bytes16(utmp.ut_type as u16, e));
+                out.extend_from_slice(&utmp.__ut_pad1);
+                out.extend_from_slice(&to_bytes32(utmp.ut_pid as u32, e));
+                out.extend_from_slice(&utmp.ut_line);
+                out.extend_from_slice(&utmp.ut_id);
+                out.extend_from_slice(&utmp.ut_user);
+                out.extend_from_slice(&utmp.ut_host);
+                out.extend_from_slice(&to_bytes16(utmp.ut_termination as u16, e));
+                out.extend_from_slice(&to_bytes16(utmp.ut_exit as u16, e));
+                let session = to_bytes32(utmp.ut_session as u32, e);
+                match e {
+                    Endian::Little => { out.extend_from_slice(&session); out.extend_from_slice(&[0u8; 4]); }
+                    Endian::Big => { out.extend_from_slice(&[0u8; 4]); out.extend_from_slice(&session); }
+                }
+                out.extend_from_slice(&to_bytes64(utmp.ut_time_sec as u64, e));
+                out.extend_from_slice(&to_bytes64(utmp.ut_time_usec as u64, e));
+                for word in utmp.ut_addr_v6 {
+                    out.extend_from_slice(&word.to_ne_bytes());
+                }
+                out.extend_from_slice(&utmp.__unused);
+                out.extend_from_slice(&[0u8; 4]);
+            }
+            Format::Libc5 => {
+                out.extend_from_slice(&to_bytes16(utmp.ut_type as u16, e));
+                out.extend_from_slice(&utmp.__ut_pad1);
+                out.extend_from_slice(&to_bytes32(utmp.ut_pid as u32, e));
+                out.extend_from_slice(&utmp.ut_line[..12]);
+                out.extend_from_slice(&utmp.ut_id[..2]);
+                out.extend_from_slice(&[0u8; 2]);
+                out.extend_from_slice(&to_bytes32(utmp.ut_time_sec as u32, e));
+                out.extend_from_slice(&utmp.ut_user[..8]);
+                out.extend_from_slice(&utmp.ut_host[..16]);
+                out.extend_from_slice(&utmp.ut_addr_v6[0].to_ne_bytes());
+            }
+            Format::Bsd => {
+                out.extend_from_slice(&utmp.ut_line[..8]);
+                out.extend_from_slice(&utmp.ut_user[..8]);
+                out.extend_from_slice(&utmp.ut_host[..16]);
+                out.extend_from_slice(&to_bytes32(utmp.ut_time_sec as u32, e));
+            }
+            Format::Macos => {
+                out.extend_from_slice(&utmp.ut_user);
+                out.extend_from_slice(&[0u8; 256 - UT_NAMESIZE]);
+                out.extend_from_slice(&utmp.ut_id);
+                out.extend_from_slice(&utmp.ut_line);
+                out.extend_from_slice(&to_bytes32(utmp.ut_pid as u32, e));
+                out.extend_from_slice(&to_bytes16(macos_type(utmp.ut_type as i16) as u16, e));
+                out.extend_from_slice(&utmp.__ut_pad1);
+                out.extend_from_slice(&to_bytes32(utmp.ut_time_sec as u32, e));
+                out.extend_from_slice(&to_bytes32(utmp.ut_time_usec as u32, e));
+                out.extend_from_slice(&utmp.ut_host);
+                out.extend_from_slice(&utmp.__unused);
+                out.extend_from_slice(&[0u8; 64 - 20]);
+            }
+        }
+        out
+    }
+
+    /// Guess the layout of a file from its first records, `sample` being the whole file.
+    ///
+    /// Each layout in [`Layout::all`] reads up to 64 records. A record other than EMPTY with plausible
+    /// type, pid and timestamp values counts for the layout, an implausible one against it, and the
+    /// layout with the highest count wins. Among equally plausible layouts one whose record size
+    /// divides the file size is preferred. Remaining ties, and input too short to tell, go to the native layout.
+    pub fn detect(sample: &[u8]) -> Layout {
+        Layout::detect_sized(sample, sample.len() as u64)
+    }
+
+    /// [`Layout::detect`] on the start of a file of `len` bytes, which is left where it was.
+    pub fn detect_reader<R: Read + Seek>(reader: &mut R) -> io::Result<Layout> {
+        let position = reader.stream_position()?;
+        let len = reader.seek(SeekFrom::End(0))?.saturating_sub(position);
+        reader.seek(SeekFrom::Start(position))?;
+        let size = Layout::all().iter().map(Layout::record_size).max().unwrap_or(UT_RECORDSIZE);
+        let mut sample = vec![0u8; size * DETECT_RECORDS];
+        let filled = read_block(reader, &mut sample)?;
+        reader.seek(SeekFrom::Start(position))?;
+        Ok(Layout::detect_sized(&sample[..filled], len))
+    }
+
+    fn detect_sized(sample: &[u8], len: u64) -> Layout {
+        let mut best = (Layout::native(), (0, true));
+        for layout in Layout::all() {
+            // 全0的EMPTY记录在任何布局下都合理，不计入，否则记录越短的布局得分越高。
+            let score = sample.chunks_exact(layout.record_size())
+                .take(DETECT_RECORDS)
+                .map(|block| match layout.parse(block) {
+                    Ok((_, utmp)) if detect_plausible(&utmp) => isize::from(utmp.ut_type != EMPTY),
+                    _ => -1,
+                })
+                .sum::<isize>();
+            let rank = (score, len.is_multiple_of(layout.record_size() as u64));
+            if score > 0 && rank > best.1 {
+                best = (layout, rank);
+            }
+        }
+        best.0
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::native()
+    }
+}
+
+impl fmt::Display for Layout {
+    /// The name [`Layout::from_str`] reads back, e.g. "glibc-be".
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format = match self.format {
+            Format::Glibc => "glibc",
+            Format::Musl => "musl",
+            Format::Libc5 => "libc5",
+            Format::Bsd => "bsd",
+            Format::Macos => "macos",
+        };
+        let endian = match self.endian {
+            Endian::Little => "le",
+            Endian::Big => "be",
+        };
+        write!(f, "{}-{}", format, endian)
+    }
+}
+
+/// A layout name [`Layout::from_str`] does not know.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+#[error("unknown layout {0:?}, expected glibc, glibc64, musl, libc5, bsd or macos, optionally followed by -le or -be")]
+pub struct UnknownLayout(pub String);
+
+impl FromStr for Layout {
+    type Err = UnknownLayout;
+
+    /// A format name, optionally followed by "-le" or "-be". Without it the native byte order is used.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        let (format, endian) = match lower.rsplit_once('-') {
+            Some((format, "le")) => (format, Endian::Little),
+            Some((format, "be")) => (format, Endian::Big),
+            _ => (lower.as_str(), Endian::native()),
+        };
+        let format = match format {
+            "glibc" => Format::Glibc,
+            "musl" | "glibc64" => Format::Musl,
+            "libc5" => Format::Libc5,
+            "bsd" => Format::Bsd,
+            "macos" => Format::Macos,
+            _ => return Err(UnknownLayout(s.to_string())),
+        };
+        Ok(Layout::new(format, endian))
+    }
+}
+
+/// Whether the values that differ most between byte orders look sane.
+fn detect_plausible(utmp: &Utmp) -> bool {
+    (EMPTY..=SHUTDOWN_TIME).contains(&utmp.ut_type)
+        && (0..=PID_MAX).contains(&utmp.ut_pid)
+        && (0..1_000_000).contains(&utmp.ut_time_usec)
+        // EMPTY slots may be all zero, anything else after 1990
+        && (utmp.ut_type == EMPTY || utmp.ut_time_sec >= 631_152_000)
+}
+
+fn parse_glibc(e: Endianness, i: &[u8]) -> IResult<&[u8], Utmp> {
+    // short int ut_type后有2字节填充，大端时类型在前2字节
+    let (i,ut_type) = i16(e)(i)?;
+    let (i,__ut_pad1) = array::<2>(i)?;
+    let (i,ut_pid) = i32(e)(i)?;
+    let (i,ut_line) = array::<UT_LINESIZE>(i)?;
+    let (i,ut_id) = array::<4>(i)?;
+    let (i,ut_user) = array::<UT_NAMESIZE>(i)?;
+    let (i,ut_host) = array::<UT_HOSTSIZE>(i)?;
+    let (i,ut_termination) = i16(e)(i)?;
+    let (i,ut_exit) = i16(e)(i)?;
+    let (i,ut_session) = i32(e)(i)?;
+    let (i,ut_time_sec) = map(u32(e), i64::from)(i)?;
+    let (i,ut_time_usec) = map(u32(e), i64::from)(i)?;
+    // 地址本身是网络字节序的16字节，按本机字节序取数以保证原样写回，解读见 Utmp::addr()。
+    let mut ut_addr_v6 = [0u32; 4];
+    let (i,()) = fill(u32(Endianness::Native), &mut ut_addr_v6)(i)?;
+    let (i,__unused) = array::<20>(i)?;
+
+    Ok((i, Utmp{
+        ut_type: i32::from(ut_type),
+        __ut_pad1,
+        ut_pid,
+        ut_line,
+        ut_id,
+        ut_user,
+        ut_host,
+        ut_termination,
+        ut_exit,
+        ut_session,
+        ut_time_sec,
+        ut_time_usec,
+        ut_addr_v6,
+        __unused,
+    }))
+}
+
+fn parse_musl(endian: Endian, i: &[u8]) -> IResult<&[u8], Utmp> {
+    let e = endian.nom();
+    let (i,ut_type) = i16(e)(i)?;
+    let (i,__ut_pad1) = array::<2>(i)?;
+    let (i,ut_pid) = i32(e)(i)?;
+    let (i,ut_line) = array::<UT_LINESIZE>(i)?;
+    let (i,ut_id) = array::<4>(i)?;
+    let (i,ut_user) = array::<UT_NAMESIZE>(i)?;
+    let (i,ut_host) = array::<UT_HOSTSIZE>(i)?;
+    let (i,ut_termination) = i16(e)(i)?;
+    let (i,ut_exit) = i16(e)(i)?;
+    let (i,(first, second)) = pair(i32(e), i32(e))(i)?;
+    let ut_session = if endian == Endian::Little { first } else { second };
+    let (i,ut_time_sec) = i64(e)(i)?;
+    let (i,ut_time_usec) = i64(e)(i)?;
+    let mut ut_addr_v6 = [0u32; 4];
+    let (i,()) = fill(u32(Endianness::Native), &mut ut_addr_v6)(i)?;
+    let (i,__unused) = array::<20>(i)?;
+    let (i,_) = take(4usize)(i)?;
+
+    Ok((i, Utmp{
+        ut_type: i32::from(ut_type),
+        __ut_pad1,
+        ut_pid,
+        ut_line,
+        ut_id,
+        ut_user,
+        ut_host,
+        ut_termination,
+        ut_exit,
+        ut_session,
+        ut_time_sec,
+        ut_time_usec,
+        ut_addr_v6,
+        __unused,
+    }))
+}
+
+fn parse_libc5(e: Endianness, i: &[u8]) -> IResult<&[u8], Utmp> {
+    let (i,ut_type) = i16(e)(i)?;
+    let (i,__ut_pad1) = array::<2>(i)?;
+    let (i,ut_pid) = i32(e)(i)?;
+    let (i,ut_line) = widen::<12, UT_LINESIZE>(i)?;
+    let (i,ut_id) = widen::<2, 4>(i)?;
+    let (i,_) = take(2usize)(i)?;
+    let (i,ut_time_sec) = map(i32(e), i64::from)(i)?;
+    let (i,ut_user) = widen::<8, UT_NAMESIZE>(i)?;
+    let (i,ut_host) = widen::<16, UT_HOSTSIZE>(i)?;
+    let (i,ut_addr) = u32(Endianness::Native)(i)?;
+
+    Ok((i, Utmp{
+        ut_type: i32::from(ut_type),
+        __ut_pad1,
+        ut_pid,
+        ut_line,
+        ut_id,
+        ut_user,
+        ut_host,
+        ut_termination: 0,
+        ut_exit: 0,
+        ut_session: 0,
+        ut_time_sec,
+        ut_time_usec: 0,
+        ut_addr_v6: [ut_addr, 0, 0, 0],
+        __unused: [0u8; 20],
+    }))
+}
+
+fn parse_bsd(e: Endianness, i: &[u8]) -> IResult<&[u8], Utmp> {
+    let (i,ut_line) = widen::<8, UT_LINESIZE>(i)?;
+    let (i,ut_user) = widen::<8, UT_NAMESIZE>(i)?;
+    let (i,ut_host) = widen::<16, UT_HOSTSIZE>(i)?;
+    let (i,ut_time_sec) = map(i32(e), i64::from)(i)?;
+
+    Ok((i, Utmp{
+        ut_type: bsd_type(&ut_line, &ut_user),
+        __ut_pad1: [0u8; 2],
+        ut_pid: 0,
+        ut_line,
+        ut_id: [0u8; 4],
+        ut_user,
+        ut_host,
+        ut_termination: 0,
+        ut_exit: 0,
+        ut_session: 0,
+        ut_time_sec,
+        ut_time_usec: 0,
+        ut_addr_v6: [0u32; 4],
+        __unused: [0u8; 20],
+    }))
+}
+
+/// `ut_type` of a BSD record, as `last(1)` tells it from the terminal and the name.
+fn bsd_type(line: &[u8], user: &[u8]) -> i32 {
+    let line = &line[..line.iter().position(|b| *b == 0).unwrap_or(line.len())];
+    let user = &user[..user.iter().position(|b| *b == 0).unwrap_or(user.len())];
+    match (line, user) {
+        (b"~", b"reboot") => BOOT_TIME,
+        (b"~", b"shutdown") => SHUTDOWN_TIME,
+        (b"|", _) => OLD_TIME,
+        (b"{", _) => NEW_TIME,
+        (_, [_, ..]) => USER_PROCESS,
+        ([_, ..], []) => DEAD_PROCESS,
+        _ => EMPTY,
+    }
+}
+
+fn parse_macos(e: Endianness, i: &[u8]) -> IResult<&[u8], Utmp> {
+    let (i,user) = take(256usize)(i)?;
+    let (i,ut_id) = array::<4>(i)?;
+    let (i,ut_line) = array::<UT_LINESIZE>(i)?;
+    let (i,ut_pid) = i32(e)(i)?;
+    let (i,ut_type) = map(i16(e), macos_type)(i)?;
+    let (i,__ut_pad1) = array::<2>(i)?;
+    let (i,ut_time_sec) = map(i32(e), i64::from)(i)?;
+    let (i,ut_time_usec) = map(i32(e), i64::from)(i)?;
+    let (i,ut_host) = array::<UT_HOSTSIZE>(i)?;
+    let (i,__unused) = array::<20>(i)?;
+    let (i,_) = take(64usize - 20)(i)?;
+
+    let mut ut_user = [0u8; UT_NAMESIZE];
+    ut_user.copy_from_slice(&user[..UT_NAMESIZE]);
+    Ok((i, Utmp{
+        ut_type,
+        __ut_pad1,
+        ut_pid,
+        ut_line,
+        ut_id,
+        ut_user,
+        ut_host,
+        ut_termination: 0,
+        ut_exit: 0,
+        ut_session: 0,
+        ut_time_sec,
+        ut_time_usec,
+        ut_addr_v6: [0u32; 4],
+        __unused,
+    }))
+}
+
+/// Mac OS X numbers OLD_TIME and NEW_TIME the other way round, the mapping is its own inverse.
+fn macos_type(ut_type: i16) -> i32 {
+    match i32::from(ut_type) {
+        OLD_TIME => NEW_TIME,
+        NEW_TIME => OLD_TIME,
+        other => other,
+    }
+}
+
+/// Take `N` bytes as an array.
+fn array<const N: usize>(i: &[u8]) -> IResult<&[u8], [u8; N]> {
+    map_res(take(N), <[u8; N]>::try_from)(i)
+}
+
+/// Take `N` bytes as the start of a zeroed array of `M`.
+fn widen<const N: usize, const M: usize>(i: &[u8]) -> IResult<&[u8], [u8; M]> {
+    let (i, bytes) = take(N)(i)?;
+    let mut out = [0u8; M];
+    out[..N].copy_from_slice(bytes);
+    Ok((i, out))
+}
+
+fn to_bytes16(v: u16, e: Endian) -> [u8; 2] {
+    match e {
+        Endian::Little => v.to_le_bytes(),
+        Endian::Big => v.to_be_bytes(),
+    }
+}
+
+fn to_bytes32(v: u32, e: Endian) -> [u8; 4] {
+    match e {
+        Endian::Little => v.to_le_bytes(),
+        Endian::Big => v.to_be_bytes(),
+    }
+}
+
+fn to_bytes64(v: u64, e: Endian) -> [u8; 8] {
+    match e {
+        Endian::Little => v.to_le_bytes(),
+        Endian::Big => v.to_be_bytes(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const UTMPDATA: &[u8] = include_bytes!("../../files4test/wtmp");
+
+    /// The test file as a host with layout `to` would have written it.
+    fn convert(data: &[u8], to: Layout) -> Vec<u8> {
+        Layout::native().parse_records(data).unwrap().iter().flat_map(|u| to.to_bytes(u)).collect()
+    }
+
+    /// The test file as a big-endian host would have written it.
+    fn swapped(data: &[u8]) -> Vec<u8> {
+        let native = Layout::native();
+        convert(data, Layout::new(Format::Glibc, if native.endian == Endian::Little { Endian::Big } else { Endian::Little }))
+    }
+
+    /// 类型、pid、时间、主机和地址
+    type Summary = (i32, i32, Option<SystemTime>, String, Option<IpAddr>);
+
+    fn summary(records: &[Utmp]) -> Vec<Summary> {
+        records.iter().map(|u| (u.ut_type, u.ut_pid, u.timestamp(), u.host(), u.addr())).collect()
+    }
+
+    #[test]
+    fn test_layout_round_trip() {
+        let native = Layout::native();
+        for (block, utmp) in UTMPDATA.chunks_exact(UT_RECORDSIZE).zip(native.parse_records(UTMPDATA).unwrap()) {
+            assert_eq!(native.to_bytes(&utmp), block);
+        }
+    }
+
+    #[test]
+    fn test_layout_padding_round_trip() {
+        // ut_type后的填充不为零时也要原样写回
+        let mut data = UTMPDATA.to_vec();
+        data[UT_RECORDSIZE + 2..UT_RECORDSIZE + 4].copy_from_slice(b"AB");
+        let records = crate::parse_records(&data).unwrap();
+        assert_eq!(&records[1].__ut_pad1, b"AB");
+        assert_eq!(records.iter().flat_map(|u| u.as_bytes()).collect::<Vec<_>>(), data);
+        for layout in Layout::all().into_iter().filter(|l| l.format != Format::Bsd) {
+            let converted = records.iter().flat_map(|u| layout.to_bytes(u)).collect::<Vec<_>>();
+            let back = layout.parse_records(&converted).unwrap();
+            assert_eq!(back.iter().flat_map(|u| layout.to_bytes(u)).collect::<Vec<_>>(), converted, "{}", layout);
+            assert_eq!(&back[1].__ut_pad1, b"AB", "{}", layout);
+        }
+    }
+
+    #[test]
+    fn test_layout_detect() {
+        let native = Layout::native();
+        let data = swapped(UTMPDATA);
+        assert_ne!(data, UTMPDATA);
+        assert_eq!(Layout::detect(UTMPDATA), native);
+
+        let detected = Layout::detect(&data);
+        assert_ne!(detected, native);
+        let records = detected.parse_records(&data).unwrap();
+        assert_eq!(summary(&records), summary(&native.parse_records(UTMPDATA).unwrap()));
+
+        assert_eq!(Layout::detect(&[]), native);
+        assert_eq!(Layout::detect_reader(&mut io::Cursor::new(data)).unwrap(), detected);
+    }
+
+    #[test]
+    fn test_layout_musl() {
+        let expected = summary(&Layout::native().parse_records(UTMPDATA).unwrap());
+        for endian in [Endian::Little, Endian::Big] {
+            let musl = Layout::new(Format::Musl, endian);
+            let data = convert(UTMPDATA, musl);
+            assert_eq!(data.len(), UTMPDATA.len() / UT_RECORDSIZE * MUSL_RECORDSIZE);
+            assert_eq!(Layout::detect(&data), musl);
+            let records = musl.parse_records(&data).unwrap();
+            assert_eq!(summary(&records), expected);
+            assert_eq!(records.iter().flat_map(|u| musl.to_bytes(u)).collect::<Vec<_>>(), data);
+        }
+
+        // 64位的tv_sec原样读入，2038年以后和1970年以前的时间都不截断
+        let musl = Layout::new(Format::Musl, Endian::Little);
+        let mut data = convert(UTMPDATA, musl);
+        data[344..352].copy_from_slice(&5_000_000_000i64.to_le_bytes());
+        data[MUSL_RECORDSIZE + 344..MUSL_RECORDSIZE + 352].copy_from_slice(&(-1i64).to_le_bytes());
+        let records = musl.parse_records(&data).unwrap();
+        assert_eq!((records[0].ut_time_sec, records[1].ut_time_sec), (5_000_000_000, -1));
+        assert_eq!(records.iter().flat_map(|u| musl.to_bytes(u)).collect::<Vec<_>>(), data);
+        assert_eq!("glibc64-be".parse(), Ok(Layout::new(Format::Musl, Endian::Big)));
+
+        // glibc的32位tv_sec按无符号读，可到2106年
+        let glibc = Layout::new(Format::Glibc, Endian::Little);
+        let mut data = UTMPDATA[..UT_RECORDSIZE].to_vec();
+        data[340..344].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(glibc.parse_records(&data).unwrap()[0].ut_time_sec, i64::from(u32::MAX));
+    }
+
+    #[test]
+    fn test_layout_glibc_be() {
+        // 按s390x/ppc64的glibc手工排列，不经过to_bytes
+        let mut data = Vec::new();
+        for (ut_type, pid, sec) in [(crate::BOOT_TIME as i16, 0i32, 1_660_000_000u32), (crate::USER_PROCESS as i16, 4321, 1_660_000_100)] {
+            let mut block = [0u8; UT_RECORDSIZE];
+            block[0..2].copy_from_slice(&ut_type.to_be_bytes());
+            block[4..8].copy_from_slice(&pid.to_be_bytes());
+            block[8..13].copy_from_slice(b"pts/0");
+            block[44..48].copy_from_slice(b"root");
+            block[76..84].copy_from_slice(b"10.0.0.1");
+            block[336..340].copy_from_slice(&7i32.to_be_bytes());
+            block[340..344].copy_from_slice(&sec.to_be_bytes());
+            block[344..348].copy_from_slice(&500_000u32.to_be_bytes());
+            block[348..352].copy_from_slice(&[10, 0, 0, 1]);
+            data.extend_from_slice(&block);
+        }
+        let glibc = Layout::new(Format::Glibc, Endian::Big);
+        assert_eq!(Layout::detect(&data), glibc);
+        let records = glibc.parse_records(&data).unwrap();
+        assert_eq!(summary(&records), vec![
+            (crate::BOOT_TIME, 0, Some(UNIX_EPOCH + std::time::Duration::new(1_660_000_000, 500_000_000)), "10.0.0.1".to_string(), "10.0.0.1".parse().ok()),
+            (crate::USER_PROCESS, 4321, Some(UNIX_EPOCH + std::time::Duration::new(1_660_000_100, 500_000_000)), "10.0.0.1".to_string(), "10.0.0.1".parse().ok()),
+        ]);
+        assert_eq!((records[1].user(), records[1].ut_session), ("root".to_string(), 7));
+        assert_eq!(records.iter().flat_map(|u| glibc.to_bytes(u)).collect::<Vec<_>>(), data);
+    }
+
+    #[test]
+    fn test_layout_libc5() {
+        let mut block = [0u8; LIBC5_RECORDSIZE];
+        block[0] = crate::USER_PROCESS as u8;
+        block[4..8].copy_from_slice(&1234i32.to_le_bytes());
+        block[8..12].copy_from_slice(b"tty1");
+        block[20..22].copy_from_slice(b"c1");
+        block[24..28].copy_from_slice(&900_000_000i32.to_le_bytes());
+        block[28..36].copy_from_slice(b"operator");
+        block[36..45].copy_from_slice(b"10.0.0.99");
+        block[52..56].copy_from_slice(&[10, 0, 0, 99]);
+        let libc5 = Layout::new(Format::Libc5, Endian::Little);
+        let utmp = libc5.parse_record(&block, 0).unwrap();
+        assert_eq!((utmp.ut_pid, utmp.line(), utmp.user(), utmp.host(), utmp.addr()),
+                   (1234, "tty1".to_string(), "operator".to_string(), "10.0.0.99".to_string(), "10.0.0.99".parse().ok()));
+        assert_eq!(utmp.timestamp(), Some(UNIX_EPOCH + std::time::Duration::from_secs(900_000_000)));
+        assert_eq!(libc5.to_bytes(&utmp), block);
+
+        let data = convert(UTMPDATA, libc5);
+        assert_eq!(Layout::detect(&data), libc5);
+        assert_eq!(Layout::detect_reader(&mut io::Cursor::new(&data)).unwrap(), libc5);
+        let records = libc5.parse_records(&data).unwrap();
+        // libc5没有微秒
+        assert_eq!(records.iter().map(|u| (u.ut_type, u.ut_pid, u.ut_time_sec)).collect::<Vec<_>>(),
+                   Layout::native().parse_records(UTMPDATA).unwrap().iter().map(|u| (u.ut_type, u.ut_pid, u.ut_time_sec)).collect::<Vec<_>>());
+        assert_eq!(records.iter().flat_map(|u| libc5.to_bytes(u)).collect::<Vec<_>>(), data);
+    }
+
+    #[test]
+    fn test_layout_bsd() {
+        let record = |line: &str, name: &str, host: &str, time: u32| {
+            let mut b = [0u8; BSD_RECORDSIZE];
+            b[..line.len()].copy_from_slice(line.as_bytes());
+            b[8..8 + name.len()].copy_from_slice(name.as_bytes());
+            b[16..16 + host.len()].copy_from_slice(host.as_bytes());
+            b[32..].copy_from_slice(&time.to_le_bytes());
+            b
+        };
+        let data = [
+            record("~", "reboot", "", 1_000_000_000),
+            record("ttyp0", "alice", "host.example", 1_000_000_100),
+            record("|", "", "", 1_000_000_200),
+            record("{", "", "", 1_000_000_150),
+            record("ttyp0", "", "", 1_000_000_300),
+            record("~", "shutdown", "", 1_000_000_400),
+        ].concat();
+        let bsd = Layout::new(Format::Bsd, Endian::Little);
+        assert_eq!(Layout::detect(&data), bsd);
+        let records = bsd.parse_records(&data).unwrap();
+        assert_eq!(records.iter().map(|u| u.ut_type).collect::<Vec<_>>(),
+                   vec![BOOT_TIME, USER_PROCESS, OLD_TIME, NEW_TIME, DEAD_PROCESS, SHUTDOWN_TIME]);
+        assert_eq!((records[1].user(), records[1].host()), ("alice".to_string(), "host.example".to_string()));
+        assert_eq!(records.iter().flat_map(|u| bsd.to_bytes(u)).collect::<Vec<_>>(), data);
+        assert_eq!(crate::session::sessions(records).len(), 1);
+    }
+
+    #[test]
+    fn test_layout_macos() {
+        let record = |ut_type: i16, user: &str, line: &str, pid: i32, time: i32| {
+            let mut b = [0u8; MACOS_RECORDSIZE];
+            b[..user.len()].copy_from_slice(user.as_bytes());
+            b[260..260 + line.len()].copy_from_slice(line.as_bytes());
+            b[292..296].copy_from_slice(&pid.to_le_bytes());
+            b[296..298].copy_from_slice(&ut_type.to_le_bytes());
+            b[300..304].copy_from_slice(&time.to_le_bytes());
+            b
+        };
+        let data = [
+            record(10, "utmpx-1.00", "", 0, 0),
+            record(2, "", "~", 1, 1_300_000_000),
+            record(3, "", "", 0, 1_300_000_050),
+            record(7, "bob", "ttys000", 312, 1_300_000_100),
+        ].concat();
+        let macos = Layout::new(Format::Macos, Endian::Little);
+        assert_eq!(Layout::detect(&data), macos);
+        let records = macos.parse_records(&data).unwrap();
+        // 文件中的OLD_TIME是3
+        assert_eq!(records.iter().map(|u| u.ut_type).collect::<Vec<_>>(), vec![crate::SIGNATURE, BOOT_TIME, OLD_TIME, USER_PROCESS]);
+        assert_eq!((records[3].ut_pid, records[3].user(), records[3].line()), (312, "bob".to_string(), "ttys000".to_string()));
+        assert_eq!(records.iter().flat_map(|u| macos.to_bytes(u)).collect::<Vec<_>>(), data);
+        assert_eq!(macos.peek_type(&data[MACOS_RECORDSIZE * 2..]), Some(OLD_TIME));
+    }
+
+    #[test]
+    fn test_layout_names() {
+        for layout in Layout::all() {
+            assert_eq!(layout.to_string().parse::<Layout>(), Ok(layout));
+        }
+        assert_eq!("glibc".parse::<Layout>(), Ok(Layout::native()));
+        assert_eq!("GLIBC-BE".parse::<Layout>(), Ok(Layout::new(Format::Glibc, Endian::Big)));
+        assert_eq!("musl-le".parse::<Layout>(), Ok(Layout::new(Format::Musl, Endian::Little)));
+        assert_eq!("libc5".parse::<Layout>(), Ok(Layout::new(Format::Libc5, Endian::native())));
+        assert!("solaris".parse::<Layout>().is_err());
+    }
+}
@@ -1,13 +1,30 @@
-use nom::{AsBytes, IResult};
+use std::fmt;
+use nom::IResult;
 // use nom::error::Error;
-use nom::bytes::complete::{take};
-use nom::combinator::map;
-use nom::multi::{count, many0, many_m_n};
-use nom::number::complete::i32;
+use nom::multi::many0;
 use crate::utmp::Utmp;
+pub use crate::error::Error;
+pub use crate::layout::{Endian, Format, Layout};
 
+pub mod anomaly;
+pub mod audit;
+pub mod boot;
+pub mod carve;
+mod error;
+#[cfg(test)]
+mod fixture;
+pub mod file;
+mod layout;
+pub mod reader;
+pub mod recover;
+pub mod session;
+#[cfg(feature = "serde")]
+mod text;
 pub mod ulity;
 pub mod utmp;
+pub mod utx;
+#[cfg(feature = "sqlite")]
+pub mod wtmpdb;
 
 
 /// Record does not contain valid info (formerly known as `UT_UNKNOWN` on Linux)
@@ -36,11 +53,110 @@ pub const SIGNATURE: i32 = 10;
 /// Time of system shutdown
 pub const SHUTDOWN_TIME: i32 = 11;
 
+/// Typed form of `ut_type`, see the constants above.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(i32)]
+pub enum UtType {
+    Empty = EMPTY,
+    RunLvl = RUN_LVL,
+    BootTime = BOOT_TIME,
+    NewTime = NEW_TIME,
+    OldTime = OLD_TIME,
+    InitProcess = INIT_PROCESS,
+    LoginProcess = LOGIN_PROCESS,
+    UserProcess = USER_PROCESS,
+    DeadProcess = DEAD_PROCESS,
+    Accounting = ACCOUNTING,
+    Signature = SIGNATURE,
+    ShutdownTime = SHUTDOWN_TIME,
+}
+
+/// A `ut_type` value outside `EMPTY..=SHUTDOWN_TIME`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidUtType(pub i32);
+
+impl fmt::Display for InvalidUtType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ut_type({}) is invalid.", self.0)
+    }
+}
+
+impl std::error::Error for InvalidUtType {}
+
+impl TryFrom<i32> for UtType {
+    type Error = InvalidUtType;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        Ok(match value {
+            EMPTY => UtType::Empty,
+            RUN_LVL => UtType::RunLvl,
+            BOOT_TIME => UtType::BootTime,
+            NEW_TIME => UtType::NewTime,
+            OLD_TIME => UtType::OldTime,
+            INIT_PROCESS => UtType::InitProcess,
+            LOGIN_PROCESS => UtType::LoginProcess,
+            USER_PROCESS => UtType::UserProcess,
+            DEAD_PROCESS => UtType::DeadProcess,
+            ACCOUNTING => UtType::Accounting,
+            SIGNATURE => UtType::Signature,
+            SHUTDOWN_TIME => UtType::ShutdownTime,
+            other => return Err(InvalidUtType(other)),
+        })
+    }
+}
+
+impl From<UtType> for i32 {
+    fn from(value: UtType) -> Self {
+        value as i32
+    }
+}
+
+/// The C identifier, e.g. `USER_PROCESS`.
+impl fmt::Display for UtType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            UtType::Empty => "EMPTY",
+            UtType::RunLvl => "RUN_LVL",
+            UtType::BootTime => "BOOT_TIME",
+            UtType::NewTime => "NEW_TIME",
+            UtType::OldTime => "OLD_TIME",
+            UtType::InitProcess => "INIT_PROCESS",
+            UtType::LoginProcess => "LOGIN_PROCESS",
+            UtType::UserProcess => "USER_PROCESS",
+            UtType::DeadProcess => "DEAD_PROCESS",
+            UtType::Accounting => "ACCOUNTING",
+            UtType::Signature => "SIGNATURE",
+            UtType::ShutdownTime => "SHUTDOWN_TIME",
+        })
+    }
+}
+
 pub const UT_LINESIZE: usize = 32;
 pub const UT_NAMESIZE: usize = 32;
 pub const UT_HOSTSIZE: usize = 256;
 pub const UT_RECORDSIZE: usize = 384;
+/// The first 16 bytes of every SQLite database, such as util-linux's wtmpdb.
+pub const SQLITE_MAGIC: &[u8; 16] = b"SQLite format 3\0";
 
+/// Parse the record with index `record` from `block` in the native layout, see [`Layout::parse_record`].
+pub fn parse_record(block: &[u8], record: usize) -> Result<Utmp, Error> {
+    Layout::native().parse_record(block, record)
+}
+
+/// Parse a whole utmp/wtmp/btmp file held in memory.
+///
+/// The file must be a whole number of valid records: trailing bytes after the last complete
+/// record are an [`Error::Truncated`], never a silently dropped partial record.
+/// Use [`reader::UtmpReader`] to keep the records before a failure.
+///
+/// Records are read in the native layout, use [`Layout::parse_records`] for files from other hosts.
+pub fn parse_records(data: &[u8]) -> Result<Vec<Utmp>, Error> {
+    Layout::native().parse_records(data)
+}
+
+/// nom parser over all complete records, see [`parse_records`] for one that reports failures.
+///
+/// Stops quietly at a partial trailing record, which is left in the remaining input.
 pub fn take_all_records(i: &[u8]) -> IResult<&[u8], Vec<Utmp>> {
     many0(take_one_record)(i)
 }
@@ -56,96 +172,17 @@ pub fn take_all_records_with_original_data(i: &[u8]) -> IResult<&[u8], Vec<(Vec<
 pub fn take_one_record_with_original_data(i: &[u8]) -> IResult<&[u8], (Vec<u8>, Utmp)> {
     let data:Vec<u8> = if i.len() >= UT_RECORDSIZE {
          Vec::from(&i[..UT_RECORDSIZE])
-    } else {Vec::from(&i[..]) };
-    // let data: Vec<u8> = Vec::from([0u8;384]);
-    let (i,ut_type) = take(4u32)(i)?;
-    let (i,ut_pid) = take(4u32)(i)?;
-    // let (i,ut_line) = map(take(32u32),|s: &[u8]| ulity::extract_string(s))(i)?;
-    let (i,ut_line) = take(32u32)(i)?;
-    // let (i,ut_id) = map(take(4u32),|s: &[u8]| ulity::extract_string(s))(i)?;
-    let (i,ut_id) = take(4u32)(i)?;
-    // let (i,ut_user) = map(take(32u32),|s: &[u8]| ulity::extract_string(s))(i)?;
-    let (i,ut_user) = take(32u32)(i)?;
-    // let (i,ut_host) = map(take(256u32),|s: &[u8]| ulity::extract_string(s))(i)?;
-    let (i,ut_host) = take(256u32)(i)?;
-    let (i,ut_termination) = map(take(2u32), |s: &[u8]| i16::from_ne_bytes(s.try_into().unwrap_or([0u8;2])))(i)?;
-    let (i,ut_exit) = map(take(2u32), |s: &[u8]| i16::from_ne_bytes(s.try_into().unwrap_or([0u8;2])))(i)?;
-    // let (i,ut_session) = map(take(4u32), |s: &[u8]| i32::from_ne_bytes(s.try_into().unwrap_or([0u8;4])))(i)?;
-    let (i,ut_session) = i32(nom::number::Endianness::Native)(i)?;
-    let (i,ut_time_sec) = map(take(4u32), |s: &[u8]| u32::from_ne_bytes(s.try_into().unwrap_or([0u8;4])))(i)?;
-    let (i,ut_time_usec) = map(take(4u32), |s: &[u8]| u32::from_ne_bytes(s.try_into().unwrap_or([0u8;4])))(i)?;
-    // let (i,ut_addr_v6) = count(take(4u32),4)(i)?;
-    // 刻意修改为大端取数： u32::from_be_bytes()，以纠正IP地址的异常。
-    let (i,ut_addr_v6) = count(map(take(4u32), |s: &[u8]| u32::from_be_bytes(s.try_into().unwrap_or([0u8;4]))),4)(i)?;
-    // let (i,__unused) = map(take(20u32),|s: &[u8]| ulity::extract_string(s))(i)?;
-    let (i,__unused) = take(20u32)(i)?;
-
-    Ok((i, (data,Utmp{
-        ut_type: i32::from_ne_bytes(ut_type.try_into().unwrap_or([0u8;4])),
-        // ut_type: i32::from_ne_bytes([2, 0, 0, 0]),
-        ut_pid: i32::from_ne_bytes(ut_pid.try_into().unwrap_or([0u8;4])),
-        // ut_line: [0; 32],
-        ut_line: ut_line.try_into().unwrap(),
-        ut_id: ut_id.try_into().unwrap(),
-        ut_user: ut_user.try_into().unwrap(),
-        ut_host: ut_host.try_into().unwrap(),
-        ut_termination: ut_termination,
-        ut_exit: ut_exit,
-        ut_session: ut_session,
-        ut_time_sec: ut_time_sec,
-        ut_time_usec: ut_time_usec,
-        // ut_addr_v4: ut_addr_v6[0].clone(),
-        ut_addr_v6: ut_addr_v6.try_into().unwrap(),
-        __unused: __unused.try_into().unwrap(),
-    })))
+    } else {Vec::from(i) };
+    let (i, utmp) = take_one_record(i)?;
+    Ok((i, (data, utmp)))
 }
+
+/// nom parser for one libc6 record in the native layout.
+///
+/// Never panics: input shorter than a record is a nom error, every field is copied into a
+/// fixed-size array, and no value is checked here, see [`parse_record`] for that.
 pub fn take_one_record(i: &[u8]) -> IResult<&[u8], Utmp> {
-    let (i,ut_type) = take(4u32)(i)?;
-    let (i,ut_pid) = take(4u32)(i)?;
-    // let (i,ut_line) = map(take(32u32),|s: &[u8]| ulity::extract_string(s))(i)?;
-    let (i,ut_line) = take(32u32)(i)?;
-    // let (i,ut_id) = map(take(4u32),|s: &[u8]| ulity::extract_string(s))(i)?;
-    let (i,ut_id) = take(4u32)(i)?;
-    // let (i,ut_user) = map(take(32u32),|s: &[u8]| ulity::extract_string(s))(i)?;
-    let (i,ut_user) = take(32u32)(i)?;
-    // let (i,ut_host) = map(take(256u32),|s: &[u8]| ulity::extract_string(s))(i)?;
-    let (i,ut_host) = take(256u32)(i)?;
-    let (i,ut_termination) = map(take(2u32), |s: &[u8]| i16::from_ne_bytes(s.try_into().unwrap_or([0u8;2])))(i)?;
-    let (i,ut_exit) = map(take(2u32), |s: &[u8]| i16::from_ne_bytes(s.try_into().unwrap_or([0u8;2])))(i)?;
-    // let (i,ut_session) = map(take(4u32), |s: &[u8]| i32::from_ne_bytes(s.try_into().unwrap_or([0u8;4])))(i)?;
-    let (i,ut_session) = i32(nom::number::Endianness::Native)(i)?;
-    let (i,ut_time_sec) = map(take(4u32), |s: &[u8]| u32::from_ne_bytes(s.try_into().unwrap_or([0u8;4])))(i)?;
-    let (i,ut_time_usec) = map(take(4u32), |s: &[u8]| u32::from_ne_bytes(s.try_into().unwrap_or([0u8;4])))(i)?;
-    // let (i,ut_addr_v6) = count(take(4u32),4)(i)?;
-    // let (i,ut_addr_v6) = count(map(take(4u32), |s: &[u8]| u32::from_ne_bytes(s.try_into().unwrap_or([0u8;4]))),4)(i)?;
-    let (i,ut_addr_v6) = count(map(take(4u32), |s: &[u8]| {
-        // println!("bytes: {:?}",s.try_into().unwrap_or([0u8;4]));
-        // std::io::stdout().flush().unwrap();
-        let mut ss:Vec<u8> = s.try_into().unwrap();
-        ss.reverse();
-        u32::from_le_bytes(ss.try_into().unwrap_or([0u8;4]))
-    }),4)(i)?;
-    // let (i,__unused) = map(take(20u32),|s: &[u8]| ulity::extract_string(s))(i)?;
-    let (i,__unused) = take(20u32)(i)?;
-
-    Ok((i, Utmp{
-        ut_type: i32::from_ne_bytes(ut_type.try_into().unwrap_or([0u8;4])),
-        // ut_type: i32::from_ne_bytes([2, 0, 0, 0]),
-        ut_pid: i32::from_ne_bytes(ut_pid.try_into().unwrap_or([0u8;4])),
-        // ut_line: [0; 32],
-        ut_line: ut_line.try_into().unwrap(),
-        ut_id: ut_id.try_into().unwrap(),
-        ut_user: ut_user.try_into().unwrap(),
-        ut_host: ut_host.try_into().unwrap(),
-        ut_termination: ut_termination,
-        ut_exit: ut_exit,
-        ut_session: ut_session,
-        ut_time_sec: ut_time_sec,
-        ut_time_usec: ut_time_usec,
-        // ut_addr_v4: ut_addr_v6[0].clone(),
-        ut_addr_v6: ut_addr_v6.try_into().unwrap(),
-        __unused: __unused.try_into().unwrap(),
-    }))
+    Layout::native().parse(i)
 }
 
 
@@ -179,6 +216,22 @@ mod tests {
         // assert_eq!(res2[5].ut_addr_v6, [3649615982,0,0,0]);
     }
 
+    #[test]
+    fn test_parse_records_errors() {
+        assert_eq!(parse_records(UTMPDATA).unwrap().len(), UTMPDATA.len() / UT_RECORDSIZE);
+
+        let mut data = UTMPDATA[..UT_RECORDSIZE * 2].to_vec();
+        data.extend_from_slice(&[0u8; 50]);
+        let e = parse_records(&data).unwrap_err();
+        assert_eq!((e.record(), e.offset(), e.field()), (2, (UT_RECORDSIZE * 2 + 44) as u64, Some("ut_user")));
+        assert_eq!(e.to_string(), "record 2 at offset 812: input ends inside ut_user, only 50 bytes of the record present");
+
+        data.truncate(UT_RECORDSIZE * 2);
+        data[UT_RECORDSIZE..UT_RECORDSIZE + 4].copy_from_slice(&99i32.to_ne_bytes());
+        let e = parse_records(&data).unwrap_err();
+        assert_eq!(e.to_string(), "record 1 at offset 384: ut_type is invalid, 99 is not a known record type");
+    }
+
     #[test]
     fn test_utmp_asbytes() {
         let utmp0 = ulity::hex_to_bytes("02000000000000007e000000000000000000000000000000000000000000000000000000000000007e7e00007265626f6f740000000000000000000000000000000000000000000000000000352e342e31372d323130322e3230332e362e656c3875656b2e7838365f36340000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000e8b42561d7f50e00000000000000000000000000000000000000000000000000000000000000000000000000");
@@ -203,12 +256,71 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_utmp_addr_round_trip() {
+        let cases: [(&str, Option<std::net::IpAddr>); 4] = [
+            ("00000000000000000000000000000000", None),
+            ("6eb888d9000000000000000000000000", Some("110.184.136.217".parse().unwrap())),
+            ("20010db8000000000000000000000001", Some("2001:db8::1".parse().unwrap())),
+            ("00000000000000000000ffff0a000001", Some("::ffff:10.0.0.1".parse().unwrap())),
+        ];
+        for (hex, addr) in cases {
+            let mut data = UTMPDATA[384*4..384*5].to_vec();
+            data[348..364].copy_from_slice(&ulity::hex_to_bytes(hex).unwrap());
+
+            let (_, utmp) = take_one_record(&data).ok().unwrap();
+            assert_eq!(utmp.addr(), addr);
+            assert_eq!(utmp.as_bytes(), data.as_slice());
+            let (_, (original, utmp)) = take_one_record_with_original_data(&data).ok().unwrap();
+            assert_eq!(utmp.addr(), addr);
+            assert_eq!(utmp.as_bytes_vec(), original);
+
+            let mut rebuilt = utmp.clone();
+            rebuilt.set_addr(addr);
+            assert_eq!(rebuilt.as_bytes(), data.as_slice());
+        }
+    }
+
+    #[test]
+    fn test_parse_any_input() {
+        // 与 fuzz/ 下的模糊测试相同的检查，这里只覆盖截断和全0xff两种情况。
+        let mut data = UTMPDATA[..UT_RECORDSIZE].to_vec();
+        data.extend_from_slice(&[0xffu8; UT_RECORDSIZE]);
+        for len in 0..=data.len() {
+            let input = &data[..len];
+            if let Ok((_, utmp)) = take_one_record(input) {
+                assert_eq!(&utmp.as_bytes()[..], &input[..UT_RECORDSIZE]);
+                let _ = (utmp.record_type(), utmp.user(), utmp.host(), utmp.line(), utmp.id(), utmp.timestamp(), utmp.addr());
+            }
+            assert_eq!(parse_records(input).is_ok(), len == UT_RECORDSIZE || len == 0);
+            let _ = audit::audit(input);
+        }
+    }
+
     #[test]
     fn test_utmp_record_debug_output() {
         let (_,res) = take_one_record(UTMPDATA).ok().unwrap();
         println!("{:#?}",res);
     }
 
+    #[test]
+    fn test_ut_type() {
+        for i in EMPTY..=SHUTDOWN_TIME {
+            assert_eq!(i32::from(UtType::try_from(i).unwrap()), i);
+        }
+        assert_eq!(UtType::try_from(12), Err(InvalidUtType(12)));
+        assert_eq!(UtType::try_from(-1), Err(InvalidUtType(-1)));
+        assert_eq!(UtType::UserProcess.to_string(), "USER_PROCESS");
+
+        let (_,res) = take_one_record(UTMPDATA).ok().unwrap();
+        assert_eq!(res.record_type(), Ok(UtType::BootTime));
+        assert_eq!(res.user(), "reboot");
+        assert_eq!(res.line(), "~");
+        assert_eq!(res.id(), "~~");
+        assert_eq!(res.host(), "5.4.17-2102.203.6.el8uek.x86_64");
+        assert_eq!(res.addr(), None);
+    }
+
     #[test]
     fn test_ulity_hex2bytes() {
         let res = ulity::hex_to_bytes("0x3230332e362e656c3875656b2e783836");
@@ -0,0 +1,213 @@
+use std::io::{self, Read};
+use crate::utmp::Utmp;
+use crate::{Error, Layout};
+
+
+/// Reads records one at a time from any `Read`, so memory use does not grow with the file.
+///
+/// Every `next()` asks the source for one record, wrap files in a `BufReader`.
+/// A trailing partial record ends the iteration with [`Error::Truncated`] after the complete records,
+/// a read error ends it with [`Error::Io`]. A record with an invalid field yields
+/// [`Error::InvalidField`] and the iteration goes on with the next record.
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::BufReader;
+/// use utmp::reader::UtmpReader;
+///
+/// let reader = UtmpReader::new(BufReader::new(File::open("/var/log/wtmp")?));
+/// for record in reader {
+///     println!("{:?}", record?);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct UtmpReader<R> {
+    inner: R,
+    layout: Layout,
+    buf: Vec<u8>,
+    offset: u64,
+    done: bool,
+}
+
+impl<R: Read> UtmpReader<R> {
+    /// Reads records in the native layout.
+    pub fn new(inner: R) -> Self {
+        Self::with_layout(inner, Layout::native())
+    }
+
+    /// Reads records written in `layout`, e.g. as found by [`Layout::detect_reader`].
+    pub fn with_layout(inner: R, layout: Layout) -> Self {
+        UtmpReader { inner, layout, buf: vec![0u8; layout.record_size()], offset: 0, done: false }
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Bytes consumed so far, i.e. the offset of the next record.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Iterator for UtmpReader<R> {
+    type Item = Result<Utmp, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let record = (self.offset / self.buf.len() as u64) as usize;
+        let filled = match read_block(&mut self.inner, &mut self.buf) {
+            Ok(n) => n,
+            Err(source) => {
+                self.done = true;
+                return Some(Err(Error::Io { record, offset: self.offset, source }));
+            }
+        };
+        if filled == 0 {
+            self.done = true;
+            return None;
+        }
+        let res = self.layout.parse_record(&self.buf[..filled], record);
+        self.done = filled < self.buf.len();
+        self.offset += filled as u64;
+        Some(res)
+    }
+}
+
+/// A sliding window over a `Read`, for scanners that look at every byte offset.
+pub(crate) struct Window<R> {
+    inner: R,
+    buf: Vec<u8>,
+    /// Position of the next unread byte in `buf`.
+    start: usize,
+    /// Offset in the input of `buf[start]`.
+    offset: u64,
+    /// Bytes asked from the source per read.
+    chunk: usize,
+    /// Size of the records counted in [`Error::Io`].
+    record_size: usize,
+    eof: bool,
+}
+
+impl<R: Read> Window<R> {
+    pub(crate) fn new(inner: R, chunk: usize, record_size: usize) -> Self {
+        Window { inner, buf: Vec::new(), start: 0, offset: 0, chunk, record_size, eof: false }
+    }
+
+    /// Keep at least `want` bytes buffered, unless the input has ended.
+    pub(crate) fn fill(&mut self, want: usize) -> Result<(), Error> {
+        if self.eof || self.buf.len() - self.start >= want {
+            return Ok(());
+        }
+        self.buf.drain(..self.start);
+        self.start = 0;
+        let filled = self.buf.len();
+        self.buf.resize(filled + self.chunk.max(want), 0);
+        let res = read_block(&mut self.inner, &mut self.buf[filled..]);
+        let n = res.as_ref().map_or(0, |n| *n);
+        self.buf.truncate(filled + n);
+        match res {
+            Ok(n) => self.eof = n < self.chunk.max(want),
+            Err(source) => {
+                self.eof = true;
+                let offset = self.offset + self.buf.len() as u64;
+                return Err(Error::Io { record: (offset / self.record_size as u64) as usize, offset, source });
+            }
+        }
+        Ok(())
+    }
+
+    /// The buffered bytes, starting at [`Window::offset`].
+    pub(crate) fn bytes(&self) -> &[u8] {
+        &self.buf[self.start..]
+    }
+
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub(crate) fn consume(&mut self, n: usize) {
+        let n = n.min(self.buf.len() - self.start);
+        self.start += n;
+        self.offset += n as u64;
+    }
+}
+
+/// Fill `buf` as far as the source allows, returns the number of bytes read.
+///
+/// Less than `buf.len()` means the source reached its end.
+pub(crate) fn read_block<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UT_RECORDSIZE;
+
+    /// Hands out at most 100 bytes per read, like a pipe would.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(100);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_reader_short_reads() {
+        let mut data = vec![0u8; UT_RECORDSIZE * 3];
+        for (i, block) in data.chunks_exact_mut(UT_RECORDSIZE).enumerate() {
+            block[4] = i as u8;
+        }
+        let mut reader = UtmpReader::new(Trickle(&data));
+        let pids = reader.by_ref().map(|r| r.unwrap().ut_pid).collect::<Vec<_>>();
+        assert_eq!(pids, vec![0, 1, 2]);
+        assert_eq!(reader.offset(), (UT_RECORDSIZE * 3) as u64);
+    }
+
+    #[test]
+    fn test_reader_partial_record() {
+        let data = vec![0u8; UT_RECORDSIZE + 10];
+        let res = UtmpReader::new(data.as_slice()).collect::<Vec<_>>();
+        assert_eq!(res.len(), 2);
+        assert!(res[0].is_ok());
+        match &res[1] {
+            Err(Error::Truncated { record, offset, field, len }) => {
+                assert_eq!((*record, *offset, *field, *len), (1, UT_RECORDSIZE as u64 + 8, "ut_line", 10));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_reader_invalid_type() {
+        let mut data = vec![0u8; UT_RECORDSIZE * 3];
+        data[UT_RECORDSIZE] = 42;
+        let res = UtmpReader::new(data.as_slice()).collect::<Vec<_>>();
+        assert_eq!(res.len(), 3);
+        let e = res[1].as_ref().err().unwrap();
+        assert_eq!((e.record(), e.offset(), e.field()), (1, UT_RECORDSIZE as u64, Some("ut_type")));
+        assert!(e.is_recoverable());
+        assert!(res[2].is_ok());
+    }
+}
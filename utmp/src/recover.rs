@@ -0,0 +1,179 @@
+use std::io::Read;
+use std::ops::Range;
+use crate::reader::Window;
+use crate::utmp::Utmp;
+use crate::{Error, Layout, EMPTY, SHUTDOWN_TIME};
+
+
+/// What [`Scanner`] found at a position of the input.
+#[derive(Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum Scanned {
+    /// A plausible record starting at `offset`.
+    Record { offset: u64, record: Utmp },
+    /// Bytes that are not part of any plausible record.
+    Skipped { range: Range<u64> },
+}
+
+/// Reads records like [`crate::reader::UtmpReader`], but steps over damaged regions instead of stopping.
+///
+/// As long as the records are plausible it walks them one after another. At the first
+/// implausible one it moves forward byte by byte until a block looks like a real record again,
+/// reports the bytes in between as [`Scanned::Skipped`], and goes on from there.
+/// A trailing partial record is reported as skipped too.
+///
+/// To resynchronise, a block must be a plausible non-EMPTY record with a timestamp, so that
+/// zero-filled or overwritten regions are not read as rows of EMPTY records at odd offsets.
+pub struct Scanner<R> {
+    window: Window<R>,
+    layout: Layout,
+    /// Start of the region being skipped.
+    skip_from: Option<u64>,
+}
+
+impl<R: Read> Scanner<R> {
+    /// Scans for records in the native layout.
+    pub fn new(inner: R) -> Self {
+        Self::with_layout(inner, Layout::native())
+    }
+
+    pub fn with_layout(inner: R, layout: Layout) -> Self {
+        Scanner { window: Window::new(inner, layout.record_size() * 16, layout.record_size()), layout, skip_from: None }
+    }
+}
+
+impl<R: Read> Iterator for Scanner<R> {
+    type Item = Result<Scanned, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let size = self.layout.record_size();
+            if let Err(e) = self.window.fill(size) {
+                return Some(Err(e));
+            }
+            let avail = self.window.bytes();
+            if avail.len() < size {
+                let from = self.skip_from.take().unwrap_or(self.window.offset());
+                self.window.consume(avail.len());
+                let to = self.window.offset();
+                return (from < to).then_some(Ok(Scanned::Skipped { range: from..to }));
+            }
+
+            let record = self.layout.parse(&avail[..size]).ok().map(|(_, record)| record);
+            let found = match (&record, self.skip_from) {
+                (Some(record), None) => plausible_record(record),
+                (Some(record), Some(_)) => resync_candidate(record),
+                (None, _) => false,
+            };
+            if !found {
+                self.skip_from.get_or_insert(self.window.offset());
+                self.window.consume(1);
+                continue;
+            }
+            if let Some(from) = self.skip_from.take() {
+                return Some(Ok(Scanned::Skipped { range: from..self.window.offset() }));
+            }
+            let offset = self.window.offset();
+            self.window.consume(size);
+            if let Some(record) = record {
+                return Some(Ok(Scanned::Record { offset, record }));
+            }
+        }
+    }
+}
+
+/// Whether a block looks like a record written by libc rather than damage.
+///
+/// Checks that `ut_type` is known, `ut_line`, `ut_user` and `ut_host` are NUL-terminated
+/// printable strings, `ut_id` is printable, and `ut_tv.tv_usec` is below one second.
+///
+/// `block` is read in the native layout.
+pub fn plausible(block: &[u8]) -> bool {
+    match Layout::native().parse(block) {
+        Ok((_, utmp)) => plausible_record(&utmp),
+        Err(_) => false,
+    }
+}
+
+/// [`plausible`] on a record already parsed.
+pub fn plausible_record(utmp: &Utmp) -> bool {
+    (EMPTY..=SHUTDOWN_TIME).contains(&utmp.ut_type)
+        && (0..1_000_000).contains(&utmp.ut_time_usec)
+        && is_text(&utmp.ut_line, true)
+        && is_text(&utmp.ut_id, false)
+        && is_text(&utmp.ut_user, true)
+        && is_text(&utmp.ut_host, true)
+}
+
+fn resync_candidate(utmp: &Utmp) -> bool {
+    plausible_record(utmp) && utmp.ut_type != EMPTY && utmp.ut_time_sec != 0
+}
+
+pub(crate) fn is_text(field: &[u8], terminated: bool) -> bool {
+    let end = field.iter().position(|b| *b == 0);
+    if terminated && end.is_none() {
+        return false;
+    }
+    match std::str::from_utf8(&field[..end.unwrap_or(field.len())]) {
+        Ok(s) => !s.chars().any(char::is_control),
+        Err(_) => false,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::block;
+    use crate::UT_RECORDSIZE;
+
+    fn scan(data: &[u8]) -> Vec<Scanned> {
+        Scanner::new(data).collect::<Result<Vec<_>, _>>().unwrap()
+    }
+
+    #[test]
+    fn test_scanner_resynchronises() {
+        let mut garbage = vec![0xa5u8; 100];
+        garbage[..4].copy_from_slice(&42i32.to_ne_bytes());
+        let data = [block(1), garbage, block(2), vec![0u8; UT_RECORDSIZE], block(3), vec![7u8; 10]].concat();
+
+        let res = scan(&data);
+        let summary = res.iter().map(|s| match s {
+            Scanned::Record { offset, record } => format!("{}@{}", record.ut_pid, offset),
+            Scanned::Skipped { range } => format!("skip {}..{}", range.start, range.end),
+        }).collect::<Vec<_>>();
+        // 对齐后的全0块是正常的EMPTY记录
+        assert_eq!(summary, vec!["1@0", "skip 384..484", "2@484", "0@868", "3@1252", "skip 1636..1646"]);
+    }
+
+    /// Fails every read once `0` is used up.
+    struct Failing<'a>(&'a [u8]);
+
+    impl Read for Failing<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(std::io::Error::other("bad sector"));
+            }
+            self.0.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_scanner_read_error_libc5() {
+        // 16条记录正好填满第一次读取，错误发生在第二次读取
+        let layout = Layout::new(crate::Format::Libc5, crate::Endian::native());
+        let data = vec![0u8; layout.record_size() * 17];
+        let res = Scanner::with_layout(Failing(&data), layout).collect::<Vec<_>>();
+        assert_eq!(res.len(), 17);
+        let e = res[16].as_ref().err().unwrap();
+        assert_eq!((e.record(), e.offset()), (16, layout.record_size() as u64 * 16));
+    }
+
+    #[test]
+    fn test_scanner_clean_file() {
+        let data = [block(1), block(2)].concat();
+        assert_eq!(scan(&data).len(), 2);
+        assert!(scan(&[]).is_empty());
+        assert!(matches!(scan(&[1u8; 5])[..], [Scanned::Skipped { ref range }] if *range == (0..5)));
+    }
+}
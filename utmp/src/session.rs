@@ -0,0 +1,214 @@
+use std::fmt;
+use std::time::{Duration, SystemTime};
+use crate::utmp::Utmp;
+use crate::{BOOT_TIME, DEAD_PROCESS, RUN_LVL, SHUTDOWN_TIME, USER_PROCESS};
+
+
+/// How a login session came to an end, in the words `last(1)` uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionEnd {
+    /// Closed by a matching DEAD_PROCESS record, or by a later login on the same terminal.
+    Logout,
+    /// Closed by a SHUTDOWN_TIME record, the system went down cleanly.
+    Down,
+    /// Closed by a BOOT_TIME record without any logout or shutdown before it.
+    Crash,
+    /// No closing record has been seen yet.
+    StillLoggedIn,
+}
+
+impl fmt::Display for SessionEnd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SessionEnd::Logout => "logout",
+            SessionEnd::Down => "down",
+            SessionEnd::Crash => "crash",
+            SessionEnd::StillLoggedIn => "still logged in",
+        })
+    }
+}
+
+/// A login session: one USER_PROCESS record and the record that closed it.
+#[derive(Clone, Debug)]
+pub struct Session {
+    /// The USER_PROCESS record that opened the session.
+    pub login: Utmp,
+    /// The record that closed the session, `None` while still logged in.
+    /// It can be a DEAD_PROCESS, a later USER_PROCESS on the same terminal, a BOOT_TIME or a SHUTDOWN_TIME record.
+    pub logout: Option<Utmp>,
+    /// Why the session ended.
+    pub end: SessionEnd,
+}
+
+impl Session {
+    /// Login time, `None` when the record holds an invalid time.
+    pub fn start(&self) -> Option<SystemTime> {
+        self.login.timestamp()
+    }
+
+    /// Logout time, `None` while still logged in or when the record holds an invalid time.
+    pub fn end_time(&self) -> Option<SystemTime> {
+        self.logout.as_ref().and_then(Utmp::timestamp)
+    }
+
+    /// Time between login and logout.
+    ///
+    /// `None` while still logged in, when either time is invalid, or when the clock went backwards between the two records.
+    pub fn duration(&self) -> Option<Duration> {
+        self.end_time()?.duration_since(self.start()?).ok()
+    }
+}
+
+/// The record type as `last(1)` sees it.
+///
+/// sysvinit and systemd write reboot and shutdown as RUN_LVL records on terminal "~",
+/// with the username "reboot" or "shutdown".
+pub(crate) fn effective_type(record: &Utmp) -> i32 {
+    if record.ut_line[0] == b'~' {
+        if record.ut_user.starts_with(b"shutdown") {
+            return SHUTDOWN_TIME;
+        } else if record.ut_user.starts_with(b"reboot") {
+            return BOOT_TIME;
+        } else if record.ut_user.starts_with(b"runlevel") {
+            return RUN_LVL;
+        }
+    }
+    record.ut_type
+}
+
+/// Whether a DEAD_PROCESS record closes the session opened by `login`.
+///
+/// Terminals are compared first. Records without a terminal fall back to `ut_id`, then to `ut_pid`
+/// and, when the DEAD_PROCESS record has one, `ut_session`.
+pub(crate) fn closes(login: &Utmp, dead: &Utmp) -> bool {
+    if dead.ut_line[0] != 0 {
+        dead.ut_line == login.ut_line
+    } else if dead.ut_id != [0u8; 4] {
+        dead.ut_id == login.ut_id
+    } else {
+        dead.ut_pid == login.ut_pid && (dead.ut_session == 0 || dead.ut_session == login.ut_session)
+    }
+}
+
+/// Rebuild login sessions from a record stream in file order, like `last(1)` does with wtmp.
+///
+/// A USER_PROCESS record opens a session. It is closed by the matching DEAD_PROCESS record,
+/// by the next login on the same terminal, or for every open session at once by BOOT_TIME (crash)
+/// and SHUTDOWN_TIME (down). Sessions still open at the end of the stream are still logged in.
+///
+/// The sessions are returned in login order.
+pub fn sessions<I>(records: I) -> Vec<Session>
+    where I: IntoIterator<Item=Utmp>
+{
+    // (login order, session)
+    let mut open: Vec<(usize, Session)> = Vec::new();
+    let mut closed: Vec<(usize, Session)> = Vec::new();
+    let mut logins = 0usize;
+
+    for record in records {
+        match effective_type(&record) {
+            USER_PROCESS => {
+                close_where(&mut open, &mut closed, &record, SessionEnd::Logout,
+                            |login| login.ut_line[0] != 0 && login.ut_line == record.ut_line);
+                open.push((logins, Session { login: record, logout: None, end: SessionEnd::StillLoggedIn }));
+                logins += 1;
+            }
+            DEAD_PROCESS => {
+                if let Some(position) = open.iter().rposition(|(_, s)| closes(&s.login, &record)) {
+                    let (order, mut session) = open.remove(position);
+                    session.logout = Some(record);
+                    session.end = SessionEnd::Logout;
+                    closed.push((order, session));
+                }
+            }
+            BOOT_TIME => close_where(&mut open, &mut closed, &record, SessionEnd::Crash, |_| true),
+            SHUTDOWN_TIME => close_where(&mut open, &mut closed, &record, SessionEnd::Down, |_| true),
+            _ => {}
+        }
+    }
+
+    closed.append(&mut open);
+    closed.sort_by_key(|(order, _)| *order);
+    closed.into_iter().map(|(_, s)| s).collect()
+}
+
+fn close_where<F>(open: &mut Vec<(usize, Session)>, closed: &mut Vec<(usize, Session)>, by: &Utmp, end: SessionEnd, predicate: F)
+    where F: Fn(&Utmp) -> bool
+{
+    let mut i = 0;
+    while i < open.len() {
+        if predicate(&open[i].1.login) {
+            let (order, mut session) = open.remove(i);
+            session.logout = Some(by.clone());
+            session.end = end;
+            closed.push((order, session));
+        } else {
+            i += 1;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+    use crate::LOGIN_PROCESS;
+    use crate::fixture::Record;
+
+    fn record(ut_type: i32, pid: i32, line: &str, user: &str, sec: i64) -> Utmp {
+        Record::new(ut_type, sec).pid(pid).line(line).user(user).build()
+    }
+
+    #[test]
+    fn test_sessions_end_reasons() {
+        let records = vec![
+            record(BOOT_TIME, 0, "~", "reboot", 100),
+            record(LOGIN_PROCESS, 10, "tty1", "LOGIN", 110),
+            record(USER_PROCESS, 11, "pts/0", "alice", 120),
+            record(USER_PROCESS, 12, "pts/1", "bob", 130),
+            record(DEAD_PROCESS, 11, "pts/0", "", 200),
+            record(USER_PROCESS, 13, "pts/0", "carol", 300),
+            record(RUN_LVL, 0, "~", "shutdown", 400),
+            record(BOOT_TIME, 0, "~", "reboot", 500),
+            record(USER_PROCESS, 14, "pts/2", "dave", 510),
+            record(BOOT_TIME, 0, "~", "reboot", 600),
+            record(USER_PROCESS, 15, "pts/3", "erin", 610),
+        ];
+        let res = sessions(records);
+        let summary = res.iter()
+            .map(|s| (s.login.ut_pid, s.end, s.duration().map(|d| d.as_secs())))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, vec![
+            (11, SessionEnd::Logout, Some(80)),
+            (12, SessionEnd::Down, Some(270)),
+            (13, SessionEnd::Down, Some(100)),
+            (14, SessionEnd::Crash, Some(90)),
+            (15, SessionEnd::StillLoggedIn, None),
+        ]);
+    }
+
+    #[test]
+    fn test_sessions_match_without_line() {
+        let mut login = record(USER_PROCESS, 21, "pts/4", "alice", 10);
+        login.ut_id = *b"ts/4";
+        let mut by_id = record(DEAD_PROCESS, 0, "", "", 20);
+        by_id.ut_id = *b"ts/4";
+        let res = sessions(vec![login, record(USER_PROCESS, 22, "pts/5", "bob", 15), by_id, record(DEAD_PROCESS, 22, "", "", 30)]);
+        assert_eq!(res.iter().map(|s| (s.login.ut_pid, s.end_time())).collect::<Vec<_>>(), vec![
+            (21, Some(UNIX_EPOCH + Duration::from_secs(20))),
+            (22, Some(UNIX_EPOCH + Duration::from_secs(30))),
+        ]);
+    }
+
+    #[test]
+    fn test_sessions_relogin_on_same_line() {
+        let res = sessions(vec![
+            record(USER_PROCESS, 31, "tty2", "alice", 10),
+            record(USER_PROCESS, 32, "tty2", "bob", 40),
+        ]);
+        assert_eq!(res[0].end, SessionEnd::Logout);
+        assert_eq!(res[0].logout.as_ref().map(|r| r.ut_pid), Some(32));
+        assert_eq!(res[1].end, SessionEnd::StillLoggedIn);
+    }
+}
@@ -0,0 +1,56 @@
+//! Serde form of the fixed-size string fields of [`Utmp`](crate::utmp::Utmp).
+//!
+//! A field is a string when that keeps every byte: UTF-8 text up to the first NUL and only NULs after it.
+//! Anything else, such as bytes left behind after the NUL, is an array of bytes, so nothing read from a file is lost.
+//! Both forms are read back, which needs a self-describing format such as JSON.
+use std::fmt;
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserializer, Serializer};
+
+pub fn serialize<S: Serializer, const N: usize>(bytes: &[u8; N], serializer: S) -> Result<S::Ok, S::Error> {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(N);
+    match std::str::from_utf8(&bytes[..end]) {
+        Ok(s) if bytes[end..].iter().all(|b| *b == 0) => serializer.serialize_str(s),
+        _ => serializer.collect_seq(bytes),
+    }
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(deserializer: D) -> Result<[u8; N], D::Error> {
+    deserializer.deserialize_any(FieldVisitor::<N>)
+}
+
+struct FieldVisitor<const N: usize>;
+
+impl<'de, const N: usize> Visitor<'de> for FieldVisitor<N> {
+    type Value = [u8; N];
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a string or an array of at most {} bytes", N)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        self.visit_bytes(v.as_bytes())
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        if v.len() > N {
+            return Err(E::invalid_length(v.len(), &self));
+        }
+        let mut out = [0u8; N];
+        out[..v.len()].copy_from_slice(v);
+        Ok(out)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut out = [0u8; N];
+        let mut len = 0;
+        while let Some(b) = seq.next_element::<u8>()? {
+            if len == N {
+                return Err(de::Error::invalid_length(len + 1, &self));
+            }
+            out[len] = b;
+            len += 1;
+        }
+        Ok(out)
+    }
+}
@@ -1,19 +1,27 @@
-use crate::{UT_HOSTSIZE, UT_LINESIZE, UT_NAMESIZE};
+use crate::{InvalidUtType, Layout, UtType, UT_HOSTSIZE, UT_LINESIZE, UT_NAMESIZE, UT_RECORDSIZE};
 use std::{fmt};
-use nom::AsBytes;
-use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use super::ulity;
 
 
 
 // 参考来自：https://github.com/libyal/dtformats/blob/main/documentation/Utmp%20login%20records%20format.asciidoc
 /// Linux utmp login record format.
-#[repr(C)]
+///
+/// This is the model every [`Layout`] reads into and writes from, not the layout of any file;
+/// the bytes of a record come from [`Layout::to_bytes`].
+///
+/// With the `serde` feature the string fields serialize as strings, or as byte arrays where a string would lose bytes.
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Utmp {
     /// Type of record
     /// i32, 4 bytes.
     pub ut_type: i32,
+    /// Padding after the 16-bit `ut_type` of the C structs, kept so records are written back byte for byte
+    /// `[u8; 2]`, 2 bytes, zero in a sound record.
+    pub __ut_pad1: [u8; 2],
     /// Process identifier (PID)
     /// i32, 4 bytes.
     pub ut_pid: i32,
@@ -21,17 +29,21 @@ pub struct Utmp {
     /// Contains an encoded string, which can be "\~" in combination with an username of "shutdown", "reboot" or "runlevel"
     /// String, 32 bytes.
     // pub ut_line: [u8; UT_LINESIZE],
+    #[cfg_attr(feature = "serde", serde(with = "crate::text"))]
     pub ut_line: [u8; UT_LINESIZE],
     /// Terminal indentifier, Terminal name suffix, or `inittab(5)` ID
     /// String, 4 bytes.
+    #[cfg_attr(feature = "serde", serde(with = "crate::text"))]
     pub ut_id: [u8; 4],
     /// Username
     /// Contains an encoded string, which can be empty (seen in combination with DEAD_PROCESS)
     /// String, 32 bytes.
+    #[cfg_attr(feature = "serde", serde(with = "crate::text"))]
     pub ut_user: [u8; UT_NAMESIZE],
     /// Hostname for remote login, or kernel version for run-level message
     /// Contains an encoded string, which can be empty (seein in combination with LOGIN_PROCESS) or contain other data such as "4.15.3-300.fc27.x86_64" or "/dev/tty2"
     /// String, 256 bytes.
+    #[cfg_attr(feature = "serde", serde(with = "crate::text"))]
     pub ut_host: [u8; UT_HOSTSIZE],
     /// Termination status
     /// i16, 2 bytes.
@@ -42,14 +54,14 @@ pub struct Utmp {
     /// Session ID (`getsid(2)`) used for windowing
     /// i32, 4 bytes.
     pub ut_session: i32,
-    /// Timestamp
-    /// u32, 4 bytes.
-    pub ut_time_sec: u32,
-    /// Microseconds
-    /// u32, 4 bytes.
-    pub ut_time_usec: u32,
+    /// Timestamp, seconds since the epoch
+    /// i64, 8 bytes, wide enough for every layout. glibc stores 4 bytes, read as unsigned up to 2106.
+    pub ut_time_sec: i64,
+    /// Microseconds, `0..1_000_000` in a valid record
+    /// i64, 8 bytes.
+    pub ut_time_usec: i64,
     /// Internet address of remote host; IPv4 address uses just `ut_addr_v6[0]`
-    /// `[u32; 4]`, 16 bytes.
+    /// `[u32; 4]`, 16 bytes, in network byte order. Read it with [`Utmp::addr`].
     pub ut_addr_v6: [u32; 4],
     // pub ut_addr_v4: u32,
     /// Reserved for future use
@@ -62,6 +74,7 @@ impl fmt::Debug for Utmp {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt.debug_struct("Utmp")
             .field("ut_type", &self.ut_type)
+            .field("__ut_pad1", &self.__ut_pad1)
             .field("ut_pid", &self.ut_pid)
             .field("ut_line", &ulity::extract_string(&self.ut_line))
             .field("ut_id", &ulity::extract_string(&self.ut_id))
@@ -79,74 +92,110 @@ impl fmt::Debug for Utmp {
 }
 
 impl Utmp {
+    /// Typed `ut_type`.
+    pub fn record_type(&self) -> Result<UtType, InvalidUtType> {
+        UtType::try_from(self.ut_type)
+    }
+
+    /// Username, up to the first NUL.
+    pub fn user(&self) -> String {
+        ulity::extract_string(&self.ut_user)
+    }
+
+    /// Hostname for remote login, or kernel version for run-level messages.
+    pub fn host(&self) -> String {
+        ulity::extract_string(&self.ut_host)
+    }
+
+    /// Terminal, e.g. "pts/0".
+    pub fn line(&self) -> String {
+        ulity::extract_string(&self.ut_line)
+    }
+
+    /// Terminal identifier, e.g. "ts/0".
+    pub fn id(&self) -> String {
+        ulity::extract_string(&self.ut_id)
+    }
+
+    /// `ut_tv` as a point in time.
+    ///
+    /// `None` when `ut_time_usec` is not below one second, or the time is beyond what `SystemTime` holds.
+    pub fn timestamp(&self) -> Option<SystemTime> {
+        let usec = u64::try_from(self.ut_time_usec).ok().filter(|usec| *usec < 1_000_000)?;
+        let sec = Duration::from_secs(self.ut_time_sec.unsigned_abs());
+        let time = if self.ut_time_sec < 0 { UNIX_EPOCH.checked_sub(sec) } else { UNIX_EPOCH.checked_add(sec) };
+        time?.checked_add(Duration::from_micros(usec))
+    }
+
+    /// Address of the remote host, `None` when `ut_addr_v6` is all zero.
+    ///
+    /// `ut_addr_v6` holds the address in network byte order, so its bytes in memory are the address:
+    /// an IPv4 address takes the first 4 bytes and leaves the other 12 zero, anything else is IPv6.
+    /// IPv4-mapped addresses (`::ffff:a.b.c.d`) stay IPv6, as they were written; use `IpAddr::to_canonical` to fold them.
+    pub fn addr(&self) -> Option<IpAddr> {
+        let mut bytes = [0u8; 16];
+        for (chunk, word) in bytes.chunks_exact_mut(4).zip(self.ut_addr_v6) {
+            chunk.copy_from_slice(&word.to_ne_bytes());
+        }
+        if bytes[4..] != [0u8; 12] {
+            Some(IpAddr::V6(Ipv6Addr::from(bytes)))
+        } else if bytes[..4] != [0u8; 4] {
+            Some(IpAddr::V4(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])))
+        } else {
+            None
+        }
+    }
+
+    /// Store an address the way [`Utmp::addr`] reads it back.
+    pub fn set_addr(&mut self, addr: Option<IpAddr>) {
+        let mut bytes = [0u8; 16];
+        match addr {
+            Some(IpAddr::V4(v4)) => bytes[..4].copy_from_slice(&v4.octets()),
+            Some(IpAddr::V6(v6)) => bytes = v6.octets(),
+            None => {}
+        }
+        for (word, chunk) in self.ut_addr_v6.iter_mut().zip(bytes.chunks_exact(4)) {
+            *word = u32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+    }
+
+    /// The record as laid out in the file, byte for byte what the parser read.
     pub fn as_bytes(&self) -> [u8;384] {
         // (*self).as_bytes()
-        let mut outvec = Vec::new();
-        outvec.extend_from_slice(&self.ut_type.to_ne_bytes());
-        outvec.extend_from_slice(&self.ut_pid.to_ne_bytes());
-        outvec.extend_from_slice(&self.ut_line.as_bytes());
-        outvec.extend_from_slice(&self.ut_id.as_bytes());
-        outvec.extend_from_slice(&self.ut_user.as_bytes());
-        outvec.extend_from_slice(&self.ut_host.as_bytes());
-        outvec.extend_from_slice(&self.ut_termination.to_ne_bytes());
-        outvec.extend_from_slice(&self.ut_exit.to_ne_bytes());
-        outvec.extend_from_slice(&self.ut_session.to_ne_bytes());
-        outvec.extend_from_slice(&self.ut_time_sec.to_ne_bytes());
-        outvec.extend_from_slice(&self.ut_time_usec.to_ne_bytes());
-        // outvec.extend_from_slice(&self.ut_addr_v6.iter()
-        //     .map(|a|a.to_ne_bytes())
-        //     .flatten()
-        //     .collect::<Vec<u8>>()
-        //     .try_into()    // 此处不好进行类型声明。
-        //     .unwrap()
-        // );
-        for i in 0..self.ut_addr_v6.len() {
-            outvec.extend_from_slice(&self.ut_addr_v6[i].to_ne_bytes());
+        let mut out = [0u8; UT_RECORDSIZE];
+        for (dst, src) in out.iter_mut().zip(self.as_bytes_vec()) {
+            *dst = src;
         }
-        // outvec.extend_from_slice(&self.ut_addr_v6.into());
-        // outvec.extend_from_slice(unsafe{mem::transmute_copy(&self.ut_addr_v6)});
-        // outvec.extend_from_slice(&[0u8; 16]);
-        // let mut tmpvec:Vec<u8> = Vec::new();
-        // outvec.append({
-        //     for item in &self.ut_addr_v6 {
-        //         let tmp:[u8;4] = item.to_ne_bytes();
-        //         println!("{:?}",tmp);
-        //         tmpvec.extend_from_slice(&tmp);
-        //     }
-        //     // tmpvec.into()
-        //     &mut tmpvec
-        //     // let ipv6bytes: [u8;16] = tmpvec.try_into().unwrap();
-        //     // ipv6bytes
-        // });
-
-        outvec.extend_from_slice(&[0u8; 20]);
-        // println!("outvec lenght: {}",outvec.len());
-        outvec.try_into().unwrap()
+        out
     }
 
+    /// The record in the native layout, see [`Layout::to_bytes`].
     pub fn as_bytes_vec(&self) -> Vec<u8> {
-        let mut outvec = Vec::new();
-        outvec.extend_from_slice(&self.ut_type.to_ne_bytes());
-        outvec.extend_from_slice(&self.ut_pid.to_ne_bytes());
-        outvec.extend_from_slice(&self.ut_line.as_bytes());
-        outvec.extend_from_slice(&self.ut_id.as_bytes());
-        outvec.extend_from_slice(&self.ut_user.as_bytes());
-        outvec.extend_from_slice(&self.ut_host.as_bytes());
-        outvec.extend_from_slice(&self.ut_termination.to_ne_bytes());
-        outvec.extend_from_slice(&self.ut_exit.to_ne_bytes());
-        outvec.extend_from_slice(&self.ut_session.to_ne_bytes());
-        outvec.extend_from_slice(&self.ut_time_sec.to_ne_bytes());
-        outvec.extend_from_slice(&self.ut_time_usec.to_ne_bytes());
-        for i in 0..self.ut_addr_v6.len() {
-            outvec.extend_from_slice(&self.ut_addr_v6[i].to_ne_bytes());
-        }
-        outvec.extend_from_slice(&[0u8; 20]);
-        outvec
+        Layout::native().to_bytes(self)
     }
 }
 
+#[cfg(feature = "serde")]
+#[test]
+fn test_utmp_serde() {
+    let mut utmp = crate::parse_records(include_bytes!("../../files4test/wtmp")).unwrap().remove(4);
+    utmp.__unused[0] = 1;
+    // NUL之后的残留字节不能丢
+    utmp.ut_host[20] = b'x';
+    let json = serde_json::to_string(&utmp).unwrap();
+    assert!(json.contains(r#""ut_user":"opc""#));
+    assert!(json.contains(r#""ut_host":[49,49,48,"#));
+    let back: Utmp = serde_json::from_str(&json).unwrap();
+    assert_eq!(back.as_bytes(), utmp.as_bytes());
+    assert!(serde_json::from_str::<Utmp>(&json.replace(r#""ut_id":"ts/0""#, r#""ut_id":"pts/0""#)).is_err());
+}
+
 #[test]
 fn test_utmp_record_block_size() {
-    assert_eq!(std::mem::size_of::<Utmp>(), 384);
+    // 记录大小由布局决定，as_bytes()是本机glibc记录
+    let data = include_bytes!("../../files4test/wtmp");
+    let utmp = crate::parse_records(data).unwrap().remove(0);
+    assert_eq!(utmp.as_bytes().len(), UT_RECORDSIZE);
+    assert_eq!(utmp.as_bytes()[..], data[..UT_RECORDSIZE]);
 }
 
@@ -0,0 +1,292 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use nom::IResult;
+use nom::bytes::complete::take;
+use nom::error::ErrorKind;
+use nom::number::complete::{be_u16, be_u32, be_u64, u8};
+use crate::reader::read_block;
+use crate::utmp::Utmp;
+use crate::{Error, BOOT_TIME, DEAD_PROCESS, EMPTY, INIT_PROCESS, LOGIN_PROCESS, NEW_TIME, OLD_TIME, SHUTDOWN_TIME, USER_PROCESS, UT_HOSTSIZE, UT_LINESIZE, UT_NAMESIZE};
+
+
+/// Size of `struct futx`, a record without its length prefix.
+pub const FUTX_SIZE: usize = 197;
+
+/// Fields of `struct futx` named as in [`Utmp`]: name, offset after the length prefix, size.
+const FUTX_FIELDS: [(&str, usize, usize); 7] = [
+    ("ut_type", 0, 1),
+    ("ut_tv", 1, 8),
+    ("ut_id", 9, 8),
+    ("ut_pid", 17, 4),
+    ("ut_user", 21, 32),
+    ("ut_line", 53, 16),
+    ("ut_host", 69, 128),
+];
+
+/// Records looked at by [`detect`].
+const DETECT_RECORDS: usize = 64;
+
+/// `ut_type` values of FreeBSD, in `<utmpx.h>` order, as the Linux constants.
+const UTX_TYPES: [i32; 9] = [EMPTY, BOOT_TIME, OLD_TIME, NEW_TIME, USER_PROCESS, INIT_PROCESS, LOGIN_PROCESS, DEAD_PROCESS, SHUTDOWN_TIME];
+
+/// Reads FreeBSD `/var/log/utx.log`, one record at a time.
+///
+/// Each record is a big-endian `u16` length followed by that many bytes of `struct futx`,
+/// whose trailing zero bytes are not written. `utx.active` and `utx.lastlogin` hold fixed-size
+/// records without the length and are not read by it. Integers are big-endian on every architecture
+/// and `fu_tv` counts microseconds. Bytes past [`FUTX_SIZE`] come from newer versions and are skipped.
+///
+/// `fu_id` is 8 bytes and cut to the 4 of [`Utmp::ut_id`]. Like [`crate::reader::UtmpReader`],
+/// a partial record at the end yields [`Error::Truncated`] and ends the iteration, an invalid field
+/// yields [`Error::InvalidField`] and the iteration goes on.
+pub struct UtxReader<R> {
+    inner: R,
+    record: usize,
+    offset: u64,
+    done: bool,
+}
+
+impl<R: Read> UtxReader<R> {
+    pub fn new(inner: R) -> Self {
+        UtxReader { inner, record: 0, offset: 0, done: false }
+    }
+
+    /// Bytes consumed so far, i.e. the offset of the next record.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        read_block(&mut self.inner, buf).map_err(|source| Error::Io { record: self.record, offset: self.offset, source })
+    }
+}
+
+impl<R: Read> Iterator for UtxReader<R> {
+    type Item = Result<Utmp, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        self.done = true;
+        let (record, base) = (self.record, self.offset);
+        let mut prefix = [0u8; 2];
+        let filled = match self.read(&mut prefix) {
+            Ok(n) => n,
+            Err(e) => return Some(Err(e)),
+        };
+        match filled {
+            0 => return None,
+            1 => return Some(Err(Error::Truncated { record, offset: base, field: "length", len: 1 })),
+            _ => {}
+        }
+        let mut body = vec![0u8; usize::from(u16::from_be_bytes(prefix))];
+        let filled = match self.read(&mut body) {
+            Ok(n) => n,
+            Err(e) => return Some(Err(e)),
+        };
+        if filled < body.len() {
+            let (field, start) = field_at(filled);
+            return Some(Err(Error::Truncated { record, offset: base + 2 + start as u64, field, len: 2 + filled }));
+        }
+        self.record += 1;
+        self.offset += 2 + body.len() as u64;
+        self.done = false;
+        Some(record_from(&body, record, base))
+    }
+}
+
+/// Parse a whole utx.log held in memory, see [`UtxReader`].
+pub fn parse_records(data: &[u8]) -> Result<Vec<Utmp>, Error> {
+    UtxReader::new(data).collect()
+}
+
+/// nom parser for one record with its length prefix. Never panics, and checks no value.
+pub fn parse(i: &[u8]) -> IResult<&[u8], Utmp> {
+    let (i, len) = be_u16(i)?;
+    let (rest, body) = take(len)(i)?;
+    match parse_futx(&padded(body)) {
+        Ok((_, utmp)) => Ok((rest, utmp)),
+        Err(_) => Err(nom::Err::Error(nom::error::Error::new(i, ErrorKind::TooLarge))),
+    }
+}
+
+/// The record as written to utx.log: the length, then `struct futx` without trailing zero bytes.
+pub fn to_bytes(utmp: &Utmp) -> Vec<u8> {
+    let ut_type = UTX_TYPES.iter().position(|t| *t == utmp.ut_type).unwrap_or(0) as u8;
+    let tv = (i128::from(utmp.ut_time_sec) * 1_000_000 + i128::from(utmp.ut_time_usec)) as u64;
+    let mut futx = Vec::with_capacity(FUTX_SIZE);
+    futx.push(ut_type);
+    futx.extend_from_slice(&tv.to_be_bytes());
+    futx.extend_from_slice(&utmp.ut_id);
+    futx.extend_from_slice(&[0u8; 4]);
+    futx.extend_from_slice(&(utmp.ut_pid as u32).to_be_bytes());
+    futx.extend_from_slice(&utmp.ut_user);
+    futx.extend_from_slice(&utmp.ut_line[..16]);
+    futx.extend_from_slice(&utmp.ut_host[..128]);
+    let len = futx.iter().rposition(|b| *b != 0).map_or(0, |last| last + 1);
+    futx.truncate(len);
+
+    let mut out = (len as u16).to_be_bytes().to_vec();
+    out.append(&mut futx);
+    out
+}
+
+/// Whether `sample`, the start of a file, reads as utx.log.
+///
+/// Up to 64 records must have a known type and, unless EMPTY, a timestamp after 1990,
+/// and at least one must not be EMPTY. A partial record at the end of the sample is ignored.
+pub fn detect(sample: &[u8]) -> bool {
+    let mut rest = sample;
+    let mut found = false;
+    for _ in 0..DETECT_RECORDS {
+        let utmp = match parse(rest) {
+            Ok((next, utmp)) => {
+                rest = next;
+                utmp
+            }
+            Err(_) => break,
+        };
+        if !(utmp.ut_type == EMPTY || UTX_TYPES.contains(&utmp.ut_type) && utmp.ut_time_sec >= 631_152_000) {
+            return false;
+        }
+        found |= utmp.ut_type != EMPTY;
+    }
+    found
+}
+
+/// [`detect`] on the start of a file, which is left where it was.
+pub fn detect_reader<R: Read + Seek>(reader: &mut R) -> io::Result<bool> {
+    let position = reader.stream_position()?;
+    let mut sample = vec![0u8; (2 + FUTX_SIZE) * DETECT_RECORDS];
+    let filled = read_block(reader, &mut sample)?;
+    reader.seek(SeekFrom::Start(position))?;
+    Ok(detect(&sample[..filled]))
+}
+
+/// The record with index `record` at offset `base`, from the bytes after its length.
+fn record_from(body: &[u8], record: usize, base: u64) -> Result<Utmp, Error> {
+    let invalid = |field: &'static str, reason: String| {
+        let start = FUTX_FIELDS.iter().find(|(name, _, _)| *name == field).map_or(0, |(_, start, _)| *start);
+        Error::InvalidField { record, offset: base + 2 + start as u64, field, reason }
+    };
+    let futx = padded(body);
+    let fu_type = futx[0];
+    if usize::from(fu_type) >= UTX_TYPES.len() {
+        return Err(invalid("ut_type", format!("{} is not a known record type", fu_type)));
+    }
+    // 补齐到完整长度后解析不会失败
+    parse_futx(&futx)
+        .map(|(_, utmp)| utmp)
+        .map_err(|_| Error::Truncated { record, offset: base, field: "length", len: body.len() })
+}
+
+fn parse_futx(i: &[u8]) -> IResult<&[u8], Utmp> {
+    let (i,fu_type) = u8(i)?;
+    let (i,fu_tv) = be_u64(i)?;
+    let (i,fu_id) = widen::<8, 4>(i)?;
+    let (i,ut_pid) = be_u32(i)?;
+    let (i,ut_user) = widen::<32, UT_NAMESIZE>(i)?;
+    let (i,ut_line) = widen::<16, UT_LINESIZE>(i)?;
+    let (i,ut_host) = widen::<128, UT_HOSTSIZE>(i)?;
+
+    Ok((i, Utmp{
+        ut_type: UTX_TYPES.get(usize::from(fu_type)).copied().unwrap_or(i32::from(fu_type)),
+        __ut_pad1: [0u8; 2],
+        ut_pid: ut_pid as i32,
+        ut_line,
+        ut_id: fu_id,
+        ut_user,
+        ut_host,
+        ut_termination: 0,
+        ut_exit: 0,
+        ut_session: 0,
+        // u64的微秒数除以10^6后总能放进i64
+        ut_time_sec: (fu_tv / 1_000_000) as i64,
+        ut_time_usec: (fu_tv % 1_000_000) as i64,
+        ut_addr_v6: [0u32; 4],
+        __unused: [0u8; 20],
+    }))
+}
+
+/// The bytes of a record as a whole `struct futx`: short records padded with zeros, longer ones cut.
+fn padded(body: &[u8]) -> [u8; FUTX_SIZE] {
+    let mut futx = [0u8; FUTX_SIZE];
+    let n = body.len().min(FUTX_SIZE);
+    futx[..n].copy_from_slice(&body[..n]);
+    futx
+}
+
+/// The field that byte `offset` after the length prefix belongs to, and where it starts.
+fn field_at(offset: usize) -> (&'static str, usize) {
+    FUTX_FIELDS.iter()
+        .rev()
+        .find(|(_, start, _)| *start <= offset)
+        .map_or(("ut_type", 0), |(name, start, _)| (*name, *start))
+}
+
+/// Take `N` bytes, keeping as many as fit an array of `M`.
+fn widen<const N: usize, const M: usize>(i: &[u8]) -> IResult<&[u8], [u8; M]> {
+    let (i, bytes) = take(N)(i)?;
+    let mut out = [0u8; M];
+    let n = N.min(M);
+    out[..n].copy_from_slice(&bytes[..n]);
+    Ok((i, out))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn futx(fu_type: u8, tv: u64, id: &[u8], pid: u32, user: &str, line: &str, host: &str) -> Vec<u8> {
+        let mut b = vec![0u8; FUTX_SIZE];
+        b[0] = fu_type;
+        b[1..9].copy_from_slice(&tv.to_be_bytes());
+        b[9..9 + id.len()].copy_from_slice(id);
+        b[17..21].copy_from_slice(&pid.to_be_bytes());
+        b[21..21 + user.len()].copy_from_slice(user.as_bytes());
+        b[53..53 + line.len()].copy_from_slice(line.as_bytes());
+        b[69..69 + host.len()].copy_from_slice(host.as_bytes());
+        let len = b.iter().rposition(|b| *b != 0).map_or(0, |last| last + 1);
+        b.truncate(len);
+        [(len as u16).to_be_bytes().to_vec(), b].concat()
+    }
+
+    #[test]
+    fn test_utx_records() {
+        let data = [
+            futx(1, 1_600_000_000_250_000, b"", 0, "", "", ""),
+            futx(4, 1_600_000_100_000_000, b"pts/0abc", 812, "alice", "pts/0", "192.0.2.7"),
+            futx(7, 1_600_000_200_000_000, b"pts/0abc", 812, "", "", ""),
+        ].concat();
+        assert!(detect(&data));
+        assert!(!detect(&[0u8; 384]));
+
+        let records = parse_records(&data).unwrap();
+        assert_eq!(records.iter().map(|u| u.ut_type).collect::<Vec<_>>(), vec![BOOT_TIME, USER_PROCESS, DEAD_PROCESS]);
+        assert_eq!(records[0].timestamp(), Some(UNIX_EPOCH + Duration::from_micros(1_600_000_000_250_000)));
+        assert_eq!((records[1].ut_pid, records[1].user(), records[1].line(), records[1].host(), records[1].id()),
+                   (812, "alice".to_string(), "pts/0".to_string(), "192.0.2.7".to_string(), "pts/".to_string()));
+        assert_eq!(to_bytes(&records[0]), futx(1, 1_600_000_000_250_000, b"", 0, "", "", ""));
+        assert_eq!(UtxReader::new(data.as_slice()).count(), 3);
+    }
+
+    #[test]
+    fn test_utx_errors() {
+        let mut data = [futx(4, 1_600_000_100_000_000, b"", 1, "bob", "ttyv0", ""), futx(9, 1_600_000_100_000_000, b"", 2, "", "", "")].concat();
+        let second = data.len() - futx(9, 1_600_000_100_000_000, b"", 2, "", "", "").len();
+        let res = UtxReader::new(data.as_slice()).collect::<Vec<_>>();
+        assert_eq!(res[1].as_ref().unwrap_err().to_string(),
+                   format!("record 1 at offset {}: ut_type is invalid, 9 is not a known record type", second + 2));
+
+        data.truncate(second + 10);
+        let res = UtxReader::new(data.as_slice()).collect::<Vec<_>>();
+        assert_eq!(res.len(), 2);
+        assert!(matches!(res[1], Err(Error::Truncated { record: 1, field: "ut_tv", len: 10, .. })));
+    }
+}
@@ -0,0 +1,253 @@
+use std::net::IpAddr;
+use std::path::Path;
+use rusqlite::{Connection, OpenFlags};
+use thiserror::Error;
+use crate::utmp::Utmp;
+use crate::{BOOT_TIME, DEAD_PROCESS, EMPTY, RUN_LVL, SHUTDOWN_TIME, USER_PROCESS};
+
+
+pub use crate::SQLITE_MAGIC;
+
+/// Which util-linux database a file is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// wtmpdb, `/var/lib/wtmpdb/wtmp.db`: one row per login or boot, with its logout or shutdown.
+    Wtmpdb,
+    /// lastlog2, `/var/lib/lastlog/lastlog2.db`: the last login of each user.
+    Lastlog2,
+}
+
+/// Why a database could not be read.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum DbError {
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+
+    /// Neither a `wtmp` nor a `Lastlog2` table.
+    #[error("not a wtmpdb or lastlog2 database")]
+    UnknownSchema,
+
+    /// A column holds a value the record model does not allow.
+    #[error("{table} row {id}: {column} is invalid, {reason}")]
+    InvalidColumn { table: &'static str, id: String, column: &'static str, reason: String },
+}
+
+/// A wtmpdb or lastlog2 database opened read-only.
+///
+/// The file is opened as immutable: SQLite takes no locks and writes nothing, not even a
+/// journal or `-shm` file, so the evidence stays as it was. Changes made while it is open are not seen.
+pub struct Database {
+    conn: Connection,
+    kind: Kind,
+}
+
+impl Database {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Database, DbError> {
+        let uri = format!("file:{}?immutable=1", escape(&path.as_ref().to_string_lossy()));
+        let conn = Connection::open_with_flags(uri, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX)?;
+        let table = |name: &str| -> Result<bool, DbError> {
+            let count: i64 = conn.query_row("SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = ?1", [name], |row| row.get(0))?;
+            Ok(count > 0)
+        };
+        let kind = if table("wtmp")? {
+            Kind::Wtmpdb
+        } else if table("Lastlog2")? {
+            Kind::Lastlog2
+        } else {
+            return Err(DbError::UnknownSchema);
+        };
+        Ok(Database { conn, kind })
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    /// The database as the records a wtmp file would hold, in time order.
+    ///
+    /// A wtmpdb row becomes two records: a login as USER_PROCESS and its logout as DEAD_PROCESS,
+    /// a boot as BOOT_TIME on terminal "~" with the user "reboot" and its end as SHUTDOWN_TIME with
+    /// the user "shutdown". The row ID is kept in [`Utmp::ut_session`] so sessions without a terminal
+    /// pair up, and the service (sshd, login, ...) is not kept. A lastlog2 row becomes one USER_PROCESS record.
+    ///
+    /// wtmpdb has no process IDs, so `ut_pid` is 0.
+    pub fn records(&self) -> Result<Vec<Utmp>, DbError> {
+        let mut records = match self.kind {
+            Kind::Wtmpdb => self.wtmpdb()?,
+            Kind::Lastlog2 => self.lastlog2()?,
+        };
+        records.sort_by_key(|utmp| (utmp.ut_time_sec, utmp.ut_time_usec));
+        Ok(records)
+    }
+
+    fn wtmpdb(&self) -> Result<Vec<Utmp>, DbError> {
+        let mut stmt = self.conn.prepare("SELECT ID, Type, User, Login, Logout, TTY, RemoteHost FROM wtmp ORDER BY ID")?;
+        let mut rows = stmt.query([])?;
+        let mut records = Vec::new();
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            let invalid = |column, reason: String| DbError::InvalidColumn { table: "wtmp", id: id.to_string(), column, reason };
+            let ut_type: i64 = row.get(1)?;
+            let user: String = row.get(2)?;
+            let login: Option<i64> = row.get(3)?;
+            let logout: Option<i64> = row.get(4)?;
+            let tty: Option<String> = row.get(5)?;
+            let host: Option<String> = row.get(6)?;
+            let tty = tty.unwrap_or_default();
+            let host = host.unwrap_or_default();
+
+            let login_at = micros(login.unwrap_or(0));
+            let logout_at = micros(logout.unwrap_or(0));
+            // wtmpdb的类型: 0 EMPTY, 1 BOOT_TIME, 2 RUN_LVL, 3 USER_PROCESS
+            let (opened, closed) = match ut_type {
+                0 => (record(EMPTY, "", "", "", login_at), None),
+                1 => (record(BOOT_TIME, "~", "reboot", &host, login_at), Some(record(SHUTDOWN_TIME, "~", "shutdown", &host, logout_at))),
+                2 => (record(RUN_LVL, "~", &user, &host, login_at), None),
+                3 => (record(USER_PROCESS, &tty, &user, &host, login_at), Some(record(DEAD_PROCESS, &tty, "", "", logout_at))),
+                other => return Err(invalid("Type", format!("{} is not a known record type", other))),
+            };
+            for mut utmp in std::iter::once(opened).chain(closed.filter(|_| logout.is_some())) {
+                utmp.ut_session = id as i32;
+                records.push(utmp);
+            }
+        }
+        Ok(records)
+    }
+
+    fn lastlog2(&self) -> Result<Vec<Utmp>, DbError> {
+        let mut stmt = self.conn.prepare("SELECT Name, Time, TTY, RemoteHost FROM Lastlog2 ORDER BY Name")?;
+        let mut rows = stmt.query([])?;
+        let mut records = Vec::new();
+        while let Some(row) = rows.next()? {
+            let name: String = row.get(0)?;
+            let time: i64 = row.get(1)?;
+            let tty: Option<String> = row.get(2)?;
+            let host: Option<String> = row.get(3)?;
+            records.push(record(USER_PROCESS, &tty.unwrap_or_default(), &name, &host.unwrap_or_default(), (time, 0)));
+        }
+        Ok(records)
+    }
+}
+
+/// Seconds and microseconds of a time in microseconds since the epoch, also before it.
+fn micros(usec: i64) -> (i64, i64) {
+    (usec.div_euclid(1_000_000), usec.rem_euclid(1_000_000))
+}
+
+/// A record with the given strings, cut to the field sizes. A host that is an address also sets `ut_addr_v6`.
+fn record(ut_type: i32, line: &str, user: &str, host: &str, (sec, usec): (i64, i64)) -> Utmp {
+    let mut utmp = Utmp {
+        ut_type,
+        __ut_pad1: [0u8; 2],
+        ut_pid: 0,
+        ut_line: [0u8; crate::UT_LINESIZE],
+        ut_id: [0u8; 4],
+        ut_user: [0u8; crate::UT_NAMESIZE],
+        ut_host: [0u8; crate::UT_HOSTSIZE],
+        ut_termination: 0,
+        ut_exit: 0,
+        ut_session: 0,
+        ut_time_sec: sec,
+        ut_time_usec: usec,
+        ut_addr_v6: [0u32; 4],
+        __unused: [0u8; 20],
+    };
+    copy(&mut utmp.ut_line, line);
+    copy(&mut utmp.ut_user, user);
+    copy(&mut utmp.ut_host, host);
+    utmp.set_addr(host.parse::<IpAddr>().ok());
+    utmp
+}
+
+/// Copy a string into a fixed-size field, keeping a NUL at the end.
+fn copy(field: &mut [u8], s: &str) {
+    let n = s.len().min(field.len() - 1);
+    field[..n].copy_from_slice(&s.as_bytes()[..n]);
+}
+
+/// Escape the characters that mean something in an SQLite URI.
+fn escape(path: &str) -> String {
+    path.chars().map(|c| match c {
+        '%' => "%25".to_string(),
+        '?' => "%3f".to_string(),
+        '#' => "%23".to_string(),
+        c => c.to_string(),
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::SessionEnd;
+    use std::path::PathBuf;
+
+    /// A database in the temporary directory, created by `sql`.
+    fn create(name: &str, sql: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("xtrace-{}-{}.db", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        Connection::open(&path).unwrap().execute_batch(sql).unwrap();
+        path
+    }
+
+    const WTMP: &str = "CREATE TABLE wtmp(ID INTEGER PRIMARY KEY, Type INTEGER, User TEXT NOT NULL, Login INTEGER, Logout INTEGER, TTY TEXT, RemoteHost TEXT, Service TEXT);";
+    const LASTLOG2: &str = "CREATE TABLE Lastlog2(Name TEXT PRIMARY KEY, Time INTEGER NOT NULL, TTY TEXT, RemoteHost TEXT, Service TEXT);";
+
+    #[test]
+    fn test_wtmpdb() {
+        let path = create("wtmpdb", &[WTMP,
+            "INSERT INTO wtmp VALUES(1, 1, 'reboot', 1700000000000000, 1700009000000000, '~', '6.4.0-1-default', NULL);",
+            "INSERT INTO wtmp VALUES(2, 3, 'alice', 1700000100250000, 1700000200000000, NULL, '192.0.2.7', 'sshd');",
+            "INSERT INTO wtmp VALUES(3, 3, 'bob', 1700000150000000, NULL, NULL, '192.0.2.8', 'sshd');"].concat());
+        let before = std::fs::read(&path).unwrap();
+        let db = Database::open(&path).unwrap();
+        assert_eq!(db.kind(), Kind::Wtmpdb);
+        let records = db.records().unwrap();
+        assert_eq!(records.iter().map(|u| (u.ut_type, u.ut_time_sec)).collect::<Vec<_>>(),
+                   vec![(BOOT_TIME, 1_700_000_000), (USER_PROCESS, 1_700_000_100), (USER_PROCESS, 1_700_000_150),
+                        (DEAD_PROCESS, 1_700_000_200), (SHUTDOWN_TIME, 1_700_009_000)]);
+        assert_eq!(records[1].ut_time_usec, 250_000);
+        assert_eq!(records[1].addr(), "192.0.2.7".parse().ok());
+        assert_eq!(records[0].host(), "6.4.0-1-default");
+
+        // 没有终端的会话按行ID配对
+        let sessions = crate::session::sessions(records);
+        assert_eq!(sessions.iter().map(|s| (s.login.user(), s.end)).collect::<Vec<_>>(),
+                   vec![("alice".to_string(), SessionEnd::Logout), ("bob".to_string(), SessionEnd::Down)]);
+        drop(db);
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_lastlog2() {
+        let path = create("lastlog2", &[LASTLOG2,
+            "INSERT INTO Lastlog2 VALUES('root', 1700000000, 'tty1', NULL, 'login');",
+            "INSERT INTO Lastlog2 VALUES('alice', 1690000000, 'ssh', '2001:db8::1', 'sshd');"].concat());
+        let db = Database::open(&path).unwrap();
+        assert_eq!(db.kind(), Kind::Lastlog2);
+        let records = db.records().unwrap();
+        assert_eq!(records.iter().map(|u| (u.user(), u.line(), u.addr())).collect::<Vec<_>>(),
+                   vec![("alice".to_string(), "ssh".to_string(), "2001:db8::1".parse().ok()), ("root".to_string(), "tty1".to_string(), None)]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_database_errors() {
+        let path = create("invalid", &[WTMP, "INSERT INTO wtmp VALUES(4, 9, 'eve', 1700000000000000, NULL, NULL, NULL, NULL);"].concat());
+        assert_eq!(Database::open(&path).unwrap().records().unwrap_err().to_string(), "wtmp row 4: Type is invalid, 9 is not a known record type");
+        std::fs::remove_file(path).unwrap();
+
+        // 时间不再拒绝，留给分析报告
+        let path = create("negative", &[WTMP, "INSERT INTO wtmp VALUES(1, 3, 'eve', -1500000, NULL, 'pts/1', NULL, NULL);"].concat());
+        let records = Database::open(&path).unwrap().records().unwrap();
+        assert_eq!((records[0].ut_time_sec, records[0].ut_time_usec), (-2, 500_000));
+        std::fs::remove_file(path).unwrap();
+
+        let path = create("other", "CREATE TABLE other(x);");
+        assert!(std::fs::read(&path).unwrap().starts_with(SQLITE_MAGIC));
+        assert!(matches!(Database::open(&path), Err(DbError::UnknownSchema)));
+        std::fs::remove_file(path).unwrap();
+    }
+}